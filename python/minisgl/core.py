@@ -1,8 +1,8 @@
 from __future__ import annotations
 
 from contextlib import contextmanager
-from dataclasses import dataclass, field
-from typing import TYPE_CHECKING, List, Literal
+from dataclasses import dataclass, field, replace
+from typing import TYPE_CHECKING, ClassVar, List, Literal
 
 import torch
 
@@ -19,11 +19,51 @@ class SamplingParams:
     top_p: float = 1.0
     ignore_eos: bool = False
     max_tokens: int = 1024
+    # number of top-token logprobs to return per position; None disables
+    logprobs: int | None = None
+    # include the prompt tokens in the logprob output
+    echo: bool = False
+
+    MAX_LOGPROBS: ClassVar[int] = 20
+
+    def __post_init__(self) -> None:
+        if self.logprobs is not None:
+            assert 0 <= self.logprobs <= self.MAX_LOGPROBS, (
+                f"logprobs must be in [0, {self.MAX_LOGPROBS}], got {self.logprobs}"
+            )
 
     @property
     def is_greedy(self) -> bool:
         return (self.temperature <= 0.0 or self.top_k == 1) and self.top_p == 1.0
 
+    # preset constructors for common decoding modes, so callers don't build
+    # these field combinations by hand
+
+    @classmethod
+    def greedy(cls, **kwargs) -> SamplingParams:
+        return cls(temperature=0.0, top_k=-1, top_p=1.0, **kwargs)
+
+    @classmethod
+    def balanced(cls, **kwargs) -> SamplingParams:
+        return cls(temperature=0.7, top_k=-1, top_p=1.0, **kwargs)
+
+    @classmethod
+    def creative(cls, **kwargs) -> SamplingParams:
+        return cls(temperature=0.8, top_k=-1, top_p=0.95, **kwargs)
+
+    def clamped(self) -> SamplingParams:
+        """
+        Return a copy with out-of-range values clamped to sane ones:
+        negative temperature becomes 0, top_p is clamped to (0, 1], and greedy
+        sampling (temperature 0) makes top_k/top_p no-ops.
+        """
+        temperature = max(self.temperature, 0.0)
+        top_k = self.top_k
+        top_p = min(self.top_p, 1.0) if self.top_p > 0.0 else 1.0
+        if temperature == 0.0:
+            top_k, top_p = -1, 1.0
+        return replace(self, temperature=temperature, top_k=top_k, top_p=top_p)
+
 
 @dataclass(eq=False)
 class Req:
@@ -60,6 +100,36 @@ class Req:
     def can_decode(self) -> bool:
         return self.remain_len > 0
 
+    def trim_trailing(self, tokens: torch.Tensor) -> bool:
+        """
+        Remove a matching trailing token sequence (e.g. a previously generated
+        stop token in a regenerate flow) from `input_ids` before scheduling.
+
+        Returns whether a trim happened; the device/cached lengths are shrunk
+        to stay consistent, keeping at least one uncached token to extend.
+        """
+        n = len(tokens)
+        if n == 0 or n >= len(self.input_ids):
+            return False
+        if not torch.equal(self.input_ids[-n:], tokens.to(self.input_ids.dtype)):
+            return False
+        new_len = len(self.input_ids) - n
+        self.input_ids = self.input_ids[:new_len]
+        self.device_len = min(self.device_len, new_len)
+        self.max_device_len -= n
+        # a fully cached prompt would leave nothing to prefill
+        self.cached_len = min(self.cached_len, new_len - 1)
+        assert 0 <= self.cached_len < self.device_len <= self.max_device_len
+        return True
+
+    def is_last_prefill_chunk(self, input_len: int) -> bool:
+        """
+        Whether this scheduled chunk completes the prompt of `input_len`
+        tokens, i.e. the request can be promoted into the decode set once the
+        chunk finishes.
+        """
+        return self.device_len == input_len
+
     def __repr__(self) -> str:
         return (
             f"{type(self)}(table_idx={self.table_idx}, "
@@ -96,6 +166,33 @@ class Batch:
     def padded_size(self) -> int:
         return len(self.padded_reqs)
 
+    def validate(self) -> None:
+        """
+        Check batch invariants before execution, so scheduler bugs surface as
+        a clear error instead of silent KV corruption.
+
+        Raises:
+            ValueError: If two reqs share a page-table row or any req has
+                inconsistent lengths.
+        """
+        seen_table_idxs = set()
+        for req in self.reqs:
+            if req.table_idx in seen_table_idxs:
+                raise ValueError(f"Duplicate table_idx {req.table_idx} in batch")
+            seen_table_idxs.add(req.table_idx)
+            if not 0 <= req.cached_len <= req.device_len:
+                raise ValueError(
+                    f"Request {req.uid} has inverted lengths:"
+                    f" cached_len={req.cached_len}, device_len={req.device_len}"
+                )
+            if req.device_len > req.max_device_len:
+                raise ValueError(
+                    f"Request {req.uid} exceeds its reservation:"
+                    f" device_len={req.device_len}, max_device_len={req.max_device_len}"
+                )
+            if req.output_len <= 0:
+                raise ValueError(f"Request {req.uid} has no output_len reserved")
+
 
 @dataclass
 class Context:
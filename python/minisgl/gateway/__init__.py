@@ -0,0 +1,5 @@
+from .config import GatewayConfig
+from .launch import launch_gateway
+from .worker import WorkerPool, WorkerState
+
+__all__ = ["GatewayConfig", "launch_gateway", "WorkerPool", "WorkerState"]
@@ -0,0 +1,751 @@
+from __future__ import annotations
+
+import asyncio
+import gzip
+import json
+import random
+import zlib
+from contextlib import asynccontextmanager
+from typing import AsyncIterator, Tuple
+
+import httpx
+from fastapi import FastAPI, Request
+from fastapi.responses import JSONResponse, Response, StreamingResponse
+from starlette.background import BackgroundTask
+from minisgl.core import SamplingParams
+from minisgl.message import TokenizeMsg
+from minisgl.utils import init_logger
+
+from .config import GatewayConfig
+from .worker import WorkerPool, WorkerState
+
+logger = init_logger(__name__, "Gateway")
+
+CHAT_COMPLETIONS_PATH = "/v1/chat/completions"
+
+
+def _error_response(status_code: int, message: str, error_type: str = "gateway_error"):
+    return JSONResponse(
+        status_code=status_code,
+        content={"error": {"message": message, "type": error_type, "code": status_code}},
+    )
+
+
+def _validate_chat_body(body: bytes) -> str | None:
+    """
+    Lightweight schema check so malformed requests fail fast at the gateway
+    instead of wasting a worker round trip. Extra fields are left alone.
+    """
+    try:
+        data = json.loads(body)
+    except json.JSONDecodeError:
+        return "Request body is not valid JSON"
+    if not isinstance(data, dict):
+        return "Request body must be a JSON object"
+
+    messages = data.get("messages")
+    prompt = data.get("prompt")
+    if messages is None and prompt is None:
+        return "Either 'messages' or 'prompt' must be provided"
+    if messages is not None:
+        if not isinstance(messages, list) or len(messages) == 0:
+            return "'messages' must be a non-empty array"
+        for i, msg in enumerate(messages):
+            if not isinstance(msg, dict) or "role" not in msg or "content" not in msg:
+                return f"'messages[{i}]' must be an object with 'role' and 'content'"
+    elif not isinstance(prompt, str):
+        return "'prompt' must be a string"
+    return None
+
+
+def _normalize_sampling(data: dict) -> Tuple[bool, str | None]:
+    """
+    Clamp out-of-range sampling params in the request body via the core
+    `SamplingParams.clamped` logic. Returns `(changed, error)`: whether
+    anything was adjusted, and a message when a field has the wrong type
+    (clamping can fix a bad range, but not a string temperature).
+    """
+    for key, kinds in (
+        ("temperature", (int, float)),
+        ("top_k", int),
+        ("top_p", (int, float)),
+    ):
+        value = data.get(key)
+        if value is None:
+            continue
+        if isinstance(value, bool) or not isinstance(value, kinds):
+            noun = "an integer" if kinds is int else "a number"
+            return False, f"'{key}' must be {noun}"
+    params = SamplingParams(
+        temperature=data.get("temperature", 1.0),
+        top_k=data.get("top_k", -1),
+        top_p=data.get("top_p", 1.0),
+    )
+    clamped = params.clamped()
+    changed = False
+    for key in ("temperature", "top_k", "top_p"):
+        old, new = getattr(params, key), getattr(clamped, key)
+        if old != new:
+            data[key] = new
+            changed = True
+    return changed, None
+
+
+def _normalize_upstream_response(upstream: httpx.Response) -> Response:
+    """
+    Pass 2xx and well-formed OpenAI error envelopes through unchanged; wrap any
+    other error body into the canonical `{"error": {...}}` shape, preserving
+    the original message so clients always see one error format. A successful
+    status with an empty body is a worker bug, not a usable completion, and is
+    reported as 502 instead of confusing clients with an empty 200.
+    """
+    if upstream.status_code < 400 and not upstream.content.strip():
+        logger.warning("Upstream returned %d with an empty body", upstream.status_code)
+        return _error_response(502, "Upstream returned an empty response body", "upstream_error")
+    if upstream.status_code >= 400:
+        try:
+            data = json.loads(upstream.content)
+        except json.JSONDecodeError:
+            data = None
+        error = data.get("error") if isinstance(data, dict) else None
+        if not (isinstance(error, dict) and "message" in error and "type" in error):
+            if isinstance(data, dict) and isinstance(data.get("detail"), str):
+                message = data["detail"]
+            elif isinstance(data, dict) and isinstance(data.get("message"), str):
+                message = data["message"]
+            elif upstream.content:
+                message = upstream.content.decode(errors="replace")[:512]
+            else:
+                message = f"Upstream returned {upstream.status_code}"
+            return _error_response(upstream.status_code, message, "upstream_error")
+    return Response(
+        content=upstream.content,
+        status_code=upstream.status_code,
+        media_type=upstream.headers.get("Content-Type"),
+    )
+
+
+def _cached_tokens_from(upstream: httpx.Response) -> int | None:
+    """
+    How many prompt tokens the worker served from its prefix cache, read from
+    the response usage metadata (OpenAI-style `prompt_tokens_details` or a
+    flat `cached_tokens`). None when the worker reported nothing.
+    """
+    try:
+        data = json.loads(upstream.content)
+    except json.JSONDecodeError:
+        return None
+    usage = data.get("usage") if isinstance(data, dict) else None
+    if not isinstance(usage, dict):
+        return None
+    details = usage.get("prompt_tokens_details")
+    if isinstance(details, dict) and isinstance(details.get("cached_tokens"), int):
+        return details["cached_tokens"]
+    if isinstance(usage.get("cached_tokens"), int):
+        return usage["cached_tokens"]
+    return None
+
+
+def _merge_usage(into: dict, add: dict) -> None:
+    """
+    Accumulate one worker's usage block into the merged response. Integer
+    counters are summed, dict-valued fields (e.g. `prompt_tokens_details`) are
+    merged recursively, and anything else is kept from the first response
+    rather than summed into a TypeError.
+    """
+    for key, value in add.items():
+        if isinstance(value, dict):
+            nested = into.setdefault(key, {})
+            if isinstance(nested, dict):
+                _merge_usage(nested, value)
+        elif isinstance(value, int) and not isinstance(value, bool):
+            current = into.get(key, 0)
+            if isinstance(current, int) and not isinstance(current, bool):
+                into[key] = current + value
+        else:
+            into.setdefault(key, value)
+
+
+def _upstream_error_response(exc: httpx.TransportError) -> Response:
+    # timeouts map to 504 so load balancers can distinguish a slow worker
+    # from an unreachable one (502)
+    if isinstance(exc, httpx.TimeoutException):
+        return _error_response(504, f"Upstream timed out: {exc}", "upstream_timeout")
+    return _error_response(502, f"Upstream unreachable: {exc}", "upstream_error")
+
+
+def _check_admin(request: Request) -> Response | None:
+    """Admin routes require the configured token; without one they are disabled."""
+    config: GatewayConfig = request.app.state.config
+    if config.admin_token is None:
+        return _error_response(403, "Admin routes are disabled (no admin token configured)")
+    token = request.headers.get("X-Admin-Token")
+    if token != config.admin_token:
+        return _error_response(403, "Invalid admin token")
+    return None
+
+
+def create_gateway_app(config: GatewayConfig) -> FastAPI:
+    pool = WorkerPool(config.workers)
+    canary_pool = WorkerPool(config.canary_workers) if config.canary_workers else None
+    shadow_pool = WorkerPool(config.shadow_workers) if config.shadow_workers else None
+    model_pools = {model: WorkerPool(urls) for model, urls in config.model_workers.items()}
+
+    async def _mirror_to_shadow(request: Request, body: bytes) -> None:
+        # fire-and-forget load-test mirror: the response is discarded and any
+        # failure must never surface to the client
+        worker = shadow_pool.select() if shadow_pool is not None else None
+        if worker is None:
+            return
+        client: httpx.AsyncClient = request.app.state.client
+        try:
+            await client.post(
+                config.upstream_url(worker.url, CHAT_COMPLETIONS_PATH), content=body
+            )
+        except Exception:
+            logger.debug("Shadow mirror request to %s failed", worker.url)
+
+    def _select_worker(
+        request: Request, model_pool: WorkerPool | None = None
+    ) -> Tuple[WorkerState | None, str]:
+        """Pick a worker, splitting traffic to the canary pool when configured."""
+        if model_pool is not None:
+            # dedicated per-model pools bypass the canary split entirely
+            return model_pool.select(), "model"
+        if canary_pool is not None and config.canary_percent > 0:
+            user_id = request.headers.get("X-User-Id")
+            if user_id is not None:
+                # stable per-user split so one user sees one model version
+                bucket = zlib.crc32(user_id.encode()) % 100
+            else:
+                bucket = random.randrange(100)
+            if bucket < config.canary_percent:
+                if worker := canary_pool.select():
+                    return worker, "canary"
+        return pool.select(), "primary"
+
+    def _pinned_worker(request: Request) -> Tuple[WorkerState | None, Response | None]:
+        """
+        Resolve the X-Target-Worker debugging header: admin-gated pinning of a
+        request to one specific worker, bypassing load balancing.
+        """
+        target = request.headers.get("X-Target-Worker")
+        if target is None:
+            return None, None
+        if denied := _check_admin(request):
+            return None, denied
+        worker = pool.get(target)
+        if worker is None and canary_pool is not None:
+            worker = canary_pool.get(target)
+        if worker is None:
+            return None, _error_response(400, f"Unknown worker: {target}")
+        return worker, None
+
+    async def _warmup_workers(app: FastAPI) -> None:
+        client: httpx.AsyncClient = app.state.client
+        body = json.dumps(
+            {
+                "model": "warmup",
+                "messages": [{"role": "user", "content": "warmup"}],
+                "max_tokens": 1,
+                "stream": False,
+            }
+        ).encode()
+        for worker in pool.workers:
+            try:
+                resp = await client.post(
+                    config.upstream_url(worker.url, CHAT_COMPLETIONS_PATH),
+                    content=body,
+                    timeout=httpx.Timeout(10.0, connect=5.0),
+                )
+                logger.info("Warmed up %s: %d", worker.url, resp.status_code)
+            except httpx.TransportError as exc:
+                logger.warning("Warmup request to %s failed: %s", worker.url, exc)
+
+    @asynccontextmanager
+    async def lifespan(app: FastAPI):
+        # a client injected before startup (e.g. a transport-mocked one in
+        # tests) is kept; warmup then primes whatever client will serve traffic
+        if getattr(app.state, "client", None) is None:
+            app.state.client = httpx.AsyncClient(timeout=httpx.Timeout(300.0, connect=5.0))
+        # off the startup path, so a slow or dead worker never blocks readiness
+        warmup_task = asyncio.create_task(_warmup_workers(app)) if config.warmup else None
+        yield
+        if warmup_task is not None:
+            warmup_task.cancel()
+        await app.state.client.aclose()
+
+    app = FastAPI(title="MiniSGL Gateway", version="0.0.1", lifespan=lifespan)
+    app.state.config = config
+    app.state.pool = pool
+    # lazily loaded on first /tokenize request; tests inject a fake manager
+    app.state.tokenize_manager = None
+
+    if config.compression:
+
+        @app.middleware("http")
+        async def compress_response(request: Request, call_next):
+            response = await call_next(request)
+            if "gzip" not in request.headers.get("Accept-Encoding", ""):
+                return response
+            content_type = response.headers.get("Content-Type", "")
+            if content_type.startswith("text/event-stream"):
+                # SSE must flush per event; compressing would buffer the stream
+                return response
+            body = b"".join([chunk async for chunk in response.body_iterator])
+            if len(body) >= config.min_compress_size:
+                body = gzip.compress(body)
+                response.headers["Content-Encoding"] = "gzip"
+                response.headers["Vary"] = "Accept-Encoding"
+            response.headers["Content-Length"] = str(len(body))
+            return Response(
+                content=body,
+                status_code=response.status_code,
+                headers=response.headers,
+                background=response.background,
+            )
+
+    def _get_tokenize_manager(request: Request):
+        manager = request.app.state.tokenize_manager
+        if manager is None and config.tokenizer_path is not None:
+            from minisgl.tokenizer.tokenize import TokenizeManager, load_tokenizer
+
+            manager = TokenizeManager(load_tokenizer(config.tokenizer_path))
+            request.app.state.tokenize_manager = manager
+        return manager
+
+    def _timeout_override(request: Request) -> Tuple[float | None, Response | None]:
+        """Resolve the X-Request-Timeout-Ms header, bounded by the server cap."""
+        raw = request.headers.get("X-Request-Timeout-Ms")
+        if raw is None:
+            return None, None
+        try:
+            ms = int(raw)
+        except ValueError:
+            return None, _error_response(
+                400, "X-Request-Timeout-Ms must be an integer", "invalid_request_error"
+            )
+        if not 0 < ms <= config.max_request_timeout_ms:
+            return None, _error_response(
+                400,
+                f"X-Request-Timeout-Ms must be between 1 and {config.max_request_timeout_ms}",
+                "invalid_request_error",
+            )
+        return ms / 1000.0, None
+
+    def _fallback_completion() -> Response:
+        # graceful degradation: a syntactically valid completion carrying the
+        # configured apology text, so clients without error handling still
+        # render something sensible
+        response = JSONResponse(
+            content={
+                "object": "chat.completion",
+                "model": "fallback",
+                "choices": [
+                    {
+                        "index": 0,
+                        "message": {"role": "assistant", "content": config.fallback_message},
+                        "finish_reason": "stop",
+                    }
+                ],
+            }
+        )
+        response.headers["X-Served-By-Pool"] = "fallback"
+        return response
+
+    async def _proxy_chat(
+        request: Request, worker: WorkerState, body: bytes, timeout: float | None = None
+    ) -> Response:
+        client: httpx.AsyncClient = request.app.state.client
+        url = config.upstream_url(worker.url, CHAT_COMPLETIONS_PATH)
+        req_timeout = (
+            httpx.USE_CLIENT_DEFAULT if timeout is None else httpx.Timeout(timeout, connect=5.0)
+        )
+        stream = False
+        include_usage = False
+        try:
+            data = json.loads(body)
+            stream = bool(data.get("stream", False))
+            stream_options = data.get("stream_options")
+            if isinstance(stream_options, dict):
+                include_usage = bool(stream_options.get("include_usage", False))
+        except (json.JSONDecodeError, AttributeError):
+            pass
+
+        if not stream:
+            with pool.track(worker):
+                try:
+                    upstream = await client.post(url, content=body, headers={
+                        "Content-Type": request.headers.get("Content-Type", "application/json"),
+                    }, timeout=req_timeout)
+                except httpx.TransportError as exc:
+                    return _upstream_error_response(exc)
+            response = _normalize_upstream_response(upstream)
+            if response.status_code < 400:
+                # clients correlating latency with cache hits read this header
+                cached_tokens = _cached_tokens_from(upstream)
+                if cached_tokens is not None:
+                    response.headers["X-Cache-Cached-Tokens"] = str(cached_tokens)
+            return response
+
+        async def stream_body() -> AsyncIterator[bytes]:
+            with pool.track(worker):
+                async with client.stream(
+                    "POST", url, content=body, timeout=req_timeout
+                ) as upstream:
+                    async for chunk in upstream.aiter_bytes():
+                        yield chunk
+
+        async def stream_body_with_usage() -> AsyncIterator[bytes]:
+            # The flag itself is forwarded unchanged; we only watch the SSE
+            # stream and append a usage chunk if the worker never sent one.
+            # The engine emits one SSE chunk per generated token, so the chunk
+            # count is the completion token count; exact prompt accounting
+            # would need the tokenizer, so prompt_tokens is reported as 0.
+            completion_tokens = 0
+            saw_usage = False
+            with pool.track(worker):
+                async with client.stream(
+                    "POST", url, content=body, timeout=req_timeout
+                ) as upstream:
+                    async for line in upstream.aiter_lines():
+                        if not line:
+                            continue
+                        payload = line.removeprefix("data:").strip()
+                        if line.startswith("data:") and payload == "[DONE]":
+                            if not saw_usage:
+                                usage_chunk = {
+                                    "object": "chat.completion.chunk",
+                                    "choices": [],
+                                    "usage": {
+                                        "prompt_tokens": 0,
+                                        "completion_tokens": completion_tokens,
+                                        "total_tokens": completion_tokens,
+                                    },
+                                }
+                                yield f"data: {json.dumps(usage_chunk)}\n\n".encode()
+                            yield b"data: [DONE]\n\n"
+                            continue
+                        if line.startswith("data:"):
+                            try:
+                                chunk = json.loads(payload)
+                            except json.JSONDecodeError:
+                                chunk = None
+                            if isinstance(chunk, dict):
+                                saw_usage |= chunk.get("usage") is not None
+                                completion_tokens += sum(
+                                    1
+                                    for choice in chunk.get("choices", [])
+                                    if choice.get("delta", {}).get("content")
+                                )
+                        yield (line + "\n\n").encode()
+
+        body_fn = stream_body_with_usage if include_usage else stream_body
+        return StreamingResponse(body_fn(), media_type="text/event-stream")
+
+    async def _fan_out_n(
+        request: Request, worker: WorkerState, data: dict, n: int, timeout: float | None = None
+    ) -> Response:
+        # issue n single-completion calls against the same worker (so they
+        # share the prefix cache) and merge the choices into one response
+        client: httpx.AsyncClient = request.app.state.client
+        url = config.upstream_url(worker.url, CHAT_COMPLETIONS_PATH)
+        req_timeout = (
+            httpx.USE_CLIENT_DEFAULT if timeout is None else httpx.Timeout(timeout, connect=5.0)
+        )
+        body = json.dumps({**data, "n": 1}).encode()
+        merged: dict | None = None
+        with pool.track(worker):
+            for _ in range(n):
+                try:
+                    upstream = await client.post(url, content=body, timeout=req_timeout)
+                except httpx.TransportError as exc:
+                    return _upstream_error_response(exc)
+                if upstream.status_code != 200:
+                    return _normalize_upstream_response(upstream)
+                payload = upstream.json()
+                if merged is None:
+                    merged = payload
+                else:
+                    for choice in payload.get("choices", []):
+                        choice["index"] = len(merged["choices"])
+                        merged["choices"].append(choice)
+                    if "usage" in merged and "usage" in payload:
+                        _merge_usage(merged["usage"], payload["usage"])
+        assert merged is not None
+        return JSONResponse(content=merged)
+
+    async def _proxy_chat_with_failover(
+        request: Request, first: WorkerState, body: bytes, timeout: float | None = None
+    ) -> Response:
+        # cross-worker failover on HTTP status: a 5xx (including our own
+        # 502/504 transport mapping) is retried on the next-best worker
+        tried = {first.url}
+        response = await _proxy_chat(request, first, body, timeout)
+        while response.status_code >= 500 and len(tried) < config.max_failover_workers:
+            candidates = sorted(
+                (w for w in pool.workers if w.available and w.url not in tried),
+                key=lambda w: w.inflight,
+            )
+            if not candidates:
+                break
+            logger.warning("Worker failed with %d, failing over", response.status_code)
+            tried.add(candidates[0].url)
+            response = await _proxy_chat(request, candidates[0], body, timeout)
+        return response
+
+    @app.post(CHAT_COMPLETIONS_PATH)
+    async def chat_completions(request: Request):
+        body = await request.body()
+        if error := _validate_chat_body(body):
+            return _error_response(400, error, error_type="invalid_request_error")
+        data = json.loads(body)
+        if config.allowed_models and data.get("model") not in config.allowed_models:
+            return _error_response(
+                404, f"Model {data.get('model')!r} is not served here", "model_not_found"
+            )
+        model_pool = model_pools.get(data.get("model")) if model_pools else None
+        if model_pools and model_pool is None and not config.workers:
+            # no dedicated pool and no default pool to fall back to
+            return _error_response(
+                404, f"Model {data.get('model')!r} is not served here", "model_not_found"
+            )
+        changed, sampling_error = _normalize_sampling(data)
+        if sampling_error is not None:
+            return _error_response(400, sampling_error, "invalid_request_error")
+        if changed:
+            logger.info("Adjusted out-of-range sampling params in request body")
+            body = json.dumps(data).encode()
+        timeout, denied = _timeout_override(request)
+        if denied is not None:
+            return denied
+        if config.max_inflight > 0 and pool.total_inflight >= config.max_inflight:
+            wait_ms = pool.estimated_wait_ms(config.max_inflight)
+            return JSONResponse(
+                status_code=429,
+                content={
+                    "error": {
+                        "message": "Too many in-flight requests, retry later",
+                        "type": "rate_limit_error",
+                        "code": 429,
+                    },
+                    "estimated_wait_ms": wait_ms,
+                },
+            )
+        pinned, denied = _pinned_worker(request)
+        if denied is not None:
+            return denied
+        if pinned is not None:
+            worker, pool_name = pinned, "pinned"
+        else:
+            worker, pool_name = _select_worker(request, model_pool)
+        if worker is None:
+            if config.fallback_message is not None and not data.get("stream"):
+                return _fallback_completion()
+            return _error_response(502, "No available worker")
+        n = data.get("n", 1)
+        if isinstance(n, int) and n > 1 and not config.workers_support_n:
+            if data.get("stream"):
+                return _error_response(
+                    400, "'n' > 1 with streaming is not supported", "invalid_request_error"
+                )
+            response = await _fan_out_n(request, worker, data, n, timeout)
+        elif config.failover_on_5xx and not data.get("stream") and pool_name != "pinned":
+            response = await _proxy_chat_with_failover(request, worker, body, timeout)
+        else:
+            response = await _proxy_chat(request, worker, body, timeout)
+        if (
+            config.fallback_message is not None
+            and response.status_code >= 500
+            and not data.get("stream")
+        ):
+            # every worker (including any failover) failed; serve the static
+            # fallback rather than a 502 the client cannot display
+            logger.warning("All workers failed, serving the static fallback completion")
+            return _fallback_completion()
+        response.headers["X-Served-By-Pool"] = pool_name
+        if shadow_pool is not None and random.randrange(100) < config.shadow_percent:
+            # mirrored after the response is sent, off the client's latency path
+            response.background = BackgroundTask(_mirror_to_shadow, request, body)
+        return response
+
+    @app.post("/tokenize")
+    async def tokenize(request: Request):
+        manager = _get_tokenize_manager(request)
+        if manager is None:
+            return _error_response(503, "No tokenizer configured", "tokenizer_not_configured")
+        body = await request.json()
+        if not isinstance(body.get("text"), str):
+            return _error_response(400, "'text' must be a string", "invalid_request_error")
+        msg = TokenizeMsg(uid=0, text=body["text"], sampling_params=SamplingParams())
+        token_ids = manager.tokenize([msg])[0].tolist()
+        return {"token_ids": token_ids, "count": len(token_ids)}
+
+    @app.post("/tokenize/count")
+    async def tokenize_count(request: Request):
+        # counts only, so billing preflight never ships large id arrays
+        manager = _get_tokenize_manager(request)
+        if manager is None:
+            return _error_response(503, "No tokenizer configured", "tokenizer_not_configured")
+        body = await request.json()
+        prompts = body.get("prompts")
+        if not isinstance(prompts, list) or not all(isinstance(p, str) for p in prompts):
+            return _error_response(
+                400, "'prompts' must be an array of strings", "invalid_request_error"
+            )
+        return {"counts": manager.count_tokens_batch(prompts)}
+
+    @app.post("/tokenize/stream")
+    async def tokenize_stream(request: Request):
+        manager = _get_tokenize_manager(request)
+        if manager is None:
+            return _error_response(503, "No tokenizer configured", "tokenizer_not_configured")
+        body = await request.json()
+        if not isinstance(body.get("text"), str):
+            return _error_response(400, "'text' must be a string", "invalid_request_error")
+        batch_size = body.get("batch_size", 64)
+        if not isinstance(batch_size, int) or batch_size < 1:
+            return _error_response(
+                400, "'batch_size' must be a positive integer", "invalid_request_error"
+            )
+        msg = TokenizeMsg(uid=0, text=body["text"], sampling_params=SamplingParams())
+
+        async def stream_tokens() -> AsyncIterator[bytes]:
+            for chunk in manager.tokenize_streaming(msg, batch_size):
+                yield f"data: {json.dumps({'token_ids': chunk.tolist()})}\n\n".encode()
+            yield b"data: [DONE]\n\n"
+
+        return StreamingResponse(stream_tokens(), media_type="text/event-stream")
+
+    @app.post("/debug/render")
+    async def debug_render(request: Request):
+        # preview the exact prompt a model receives, without contacting a worker
+        manager = _get_tokenize_manager(request)
+        if manager is None:
+            return _error_response(503, "No tokenizer configured", "tokenizer_not_configured")
+        body = await request.json()
+        messages = body.get("messages")
+        if not isinstance(messages, list) or len(messages) == 0:
+            return _error_response(
+                400, "'messages' must be a non-empty array", "invalid_request_error"
+            )
+        rendered = manager.tokenizer.apply_chat_template(
+            messages,
+            tokenize=False,
+            add_generation_prompt=bool(body.get("add_generation_prompt", True)),
+        )
+        msg = TokenizeMsg(uid=0, text=rendered, sampling_params=SamplingParams())
+        return {"rendered": rendered, "count": len(manager.tokenize([msg])[0])}
+
+    @app.get("/metrics/queue")
+    async def queue_metrics():
+        # queue-depth signal for external autoscalers (HPA/KEDA); plain JSON
+        # so it can be polled without a Prometheus stack
+        return {
+            "workers": [{"url": w.url, "inflight": w.inflight} for w in pool.workers],
+            "total_inflight": pool.total_inflight,
+        }
+
+    @app.get("/healthz")
+    async def healthz():
+        return {"status": "ok", "workers": [w.describe() for w in pool.workers]}
+
+    async def _worker_is_ready(request: Request, worker: WorkerState) -> bool:
+        client: httpx.AsyncClient = request.app.state.client
+        try:
+            resp = await client.get(config.upstream_url(worker.url, "/healthz"))
+            if resp.status_code != 200:
+                return False
+            if not config.deep_health:
+                return True
+            # /healthz can pass while generation is wedged, so the deep check
+            # asks for one real token and insists on a well-formed completion
+            canary = {
+                "model": "canary",
+                "messages": [{"role": "user", "content": "ping"}],
+                "max_tokens": 1,
+                "stream": False,
+            }
+            resp = await client.post(
+                config.upstream_url(worker.url, CHAT_COMPLETIONS_PATH),
+                content=json.dumps(canary).encode(),
+            )
+            if resp.status_code != 200:
+                return False
+            payload = resp.json()
+        except (httpx.TransportError, json.JSONDecodeError):
+            return False
+        return isinstance(payload, dict) and bool(payload.get("choices"))
+
+    @app.get("/readyz")
+    async def readyz(request: Request):
+        # readiness probes every worker (shallow or deep per config) and keeps
+        # the pool's healthy flags in sync as a side effect
+        for worker in pool.workers:
+            worker.healthy = await _worker_is_ready(request, worker)
+        ready = any(w.available for w in pool.workers)
+        return JSONResponse(
+            status_code=200 if ready else 503,
+            content={
+                "status": "ready" if ready else "unready",
+                "workers": [w.describe() for w in pool.workers],
+            },
+        )
+
+    @app.get("/admin/workers")
+    async def list_workers(request: Request):
+        if denied := _check_admin(request):
+            return denied
+        return {"workers": [w.describe() for w in pool.workers]}
+
+    @app.post("/admin/workers/drain")
+    async def drain_worker(request: Request):
+        if denied := _check_admin(request):
+            return denied
+        body = await request.json()
+        if not pool.set_draining(body.get("url", ""), True):
+            return _error_response(400, f"Unknown worker: {body.get('url')}")
+        return {"status": "ok"}
+
+    # registered after the specialized handlers, so /v1/chat/completions keeps
+    # its validation/metrics and anything else under /v1 is proxied verbatim
+    @app.api_route("/v1/{path:path}", methods=["GET", "POST", "PUT", "DELETE", "PATCH"])
+    async def proxy_v1(request: Request, path: str):
+        worker, denied = _pinned_worker(request)
+        if denied is not None:
+            return denied
+        worker = worker or pool.select()
+        if worker is None:
+            return _error_response(502, "No available worker")
+        client: httpx.AsyncClient = request.app.state.client
+        url = config.upstream_url(worker.url, f"/v1/{path}")
+        if request.url.query:
+            url += "?" + request.url.query
+        headers = {
+            key: value
+            for key, value in request.headers.items()
+            if key.lower() not in ("host", "content-length")
+        }
+        with pool.track(worker):
+            try:
+                upstream = await client.request(
+                    request.method, url, content=await request.body(), headers=headers
+                )
+            except httpx.TransportError as exc:
+                return _upstream_error_response(exc)
+        return Response(
+            content=upstream.content,
+            status_code=upstream.status_code,
+            media_type=upstream.headers.get("Content-Type"),
+        )
+
+    @app.post("/admin/workers/enable")
+    async def enable_worker(request: Request):
+        if denied := _check_admin(request):
+            return denied
+        body = await request.json()
+        if not pool.set_draining(body.get("url", ""), False):
+            return _error_response(400, f"Unknown worker: {body.get('url')}")
+        return {"status": "ok"}
+
+    return app
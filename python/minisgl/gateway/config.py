@@ -0,0 +1,120 @@
+from __future__ import annotations
+
+import os
+from dataclasses import dataclass, field
+from typing import Dict, List
+
+GATEWAY_ENV_PREFIX = "MINISGL_GATEWAY_"
+
+
+def _env(name: str, default: str = "") -> str:
+    return os.getenv(GATEWAY_ENV_PREFIX + name, default)
+
+
+@dataclass
+class GatewayConfig:
+    """Configuration for the multi-worker HTTP gateway."""
+
+    workers: List[str] = field(default_factory=list)
+    host: str = "127.0.0.1"
+    port: int = 2929
+    # token required for /admin routes; when None the routes are disabled
+    admin_token: str | None = None
+    # path prefix prepended to every upstream route, for workers that expose
+    # the OpenAI API behind a proxy prefix like /openai/v1/...
+    upstream_prefix: str = ""
+    # whether workers handle `n > 1` natively; when False the gateway fans a
+    # request out as n single-completion calls and merges the choices
+    workers_support_n: bool = True
+    # canary pool for A/B testing: the given share of traffic is routed to
+    # these workers, keyed on the X-User-Id header when present so a given
+    # user sticks to one pool
+    canary_workers: List[str] = field(default_factory=list)
+    canary_percent: int = 0
+    # non-streaming chat failover: when a worker answers 5xx, retry the same
+    # request on another worker, trying at most max_failover_workers in total
+    failover_on_5xx: bool = False
+    max_failover_workers: int = 2
+    # reject new chat requests with 429 once this many are in flight across
+    # the pool; 0 disables the cap
+    max_inflight: int = 0
+    # models clients may request; an empty list allows everything
+    allowed_models: List[str] = field(default_factory=list)
+    # multi-model routing: model id -> dedicated worker pool; models not in
+    # the mapping fall back to the flat default pool, or 404 without one
+    model_workers: Dict[str, List[str]] = field(default_factory=dict)
+    # tokenizer served by the gateway's /tokenize routes; None disables them
+    tokenizer_path: str | None = None
+    # shadow pool for load testing: the given share of chat traffic is
+    # additionally mirrored (fire-and-forget) to these workers, without ever
+    # affecting the client's response
+    shadow_workers: List[str] = field(default_factory=list)
+    shadow_percent: int = 0
+    # graceful degradation: serve this text as a valid 200 completion when
+    # every worker has failed, instead of a 502; None keeps the 502
+    fallback_message: str | None = None
+    # cap on the X-Request-Timeout-Ms header, which lets a single request
+    # extend its generation timeout without reconfiguring the gateway
+    max_request_timeout_ms: int = 600_000
+    # fire a 1-token warmup chat at each worker on startup so the first real
+    # request does not pay cold-start costs (DNS, connection pool); failures
+    # are logged and never block the gateway from serving
+    warmup: bool = False
+    # readiness probing: with deep_health, /readyz also sends each worker a
+    # 1-token canary chat request, catching workers that are up but wedged
+    deep_health: bool = False
+    # gzip non-streaming responses above min_compress_size when the client
+    # accepts it; SSE streams are always passed through uncompressed
+    compression: bool = False
+    min_compress_size: int = 1024
+
+    def __post_init__(self) -> None:
+        self.workers = [w.rstrip("/") for w in self.workers]
+        self.canary_workers = [w.rstrip("/") for w in self.canary_workers]
+        self.shadow_workers = [w.rstrip("/") for w in self.shadow_workers]
+        self.model_workers = {
+            model: [w.rstrip("/") for w in workers]
+            for model, workers in self.model_workers.items()
+        }
+        assert 0 <= self.canary_percent <= 100
+        assert 0 <= self.shadow_percent <= 100
+        if self.upstream_prefix:
+            self.upstream_prefix = "/" + self.upstream_prefix.strip("/")
+
+    def upstream_url(self, worker_url: str, path: str) -> str:
+        return worker_url.rstrip("/") + self.upstream_prefix + path
+
+    @classmethod
+    def from_env(cls) -> GatewayConfig:
+        workers = [w for w in _env("WORKERS").split(",") if w]
+        # e.g. "llama=http://a:1919,http://b:1919;qwen=http://c:1919"
+        model_workers: Dict[str, List[str]] = {}
+        for entry in _env("MODEL_WORKERS").split(";"):
+            if not entry:
+                continue
+            model, _, urls = entry.partition("=")
+            model_workers[model] = [u for u in urls.split(",") if u]
+        return cls(
+            workers=workers,
+            host=_env("HOST", cls.host),
+            port=int(_env("PORT", str(cls.port))),
+            admin_token=_env("ADMIN_TOKEN") or None,
+            upstream_prefix=_env("UPSTREAM_PREFIX"),
+            workers_support_n=_env("WORKERS_SUPPORT_N", "1") not in ("0", "false"),
+            canary_workers=[w for w in _env("CANARY_WORKERS").split(",") if w],
+            canary_percent=int(_env("CANARY_PERCENT", "0")),
+            failover_on_5xx=_env("FAILOVER_ON_5XX", "0") in ("1", "true"),
+            max_failover_workers=int(_env("MAX_FAILOVER_WORKERS", "2")),
+            max_inflight=int(_env("MAX_INFLIGHT", "0")),
+            allowed_models=[m for m in _env("ALLOWED_MODELS").split(",") if m],
+            model_workers=model_workers,
+            tokenizer_path=_env("TOKENIZER_PATH") or None,
+            shadow_workers=[w for w in _env("SHADOW_WORKERS").split(",") if w],
+            shadow_percent=int(_env("SHADOW_PERCENT", "0")),
+            fallback_message=_env("FALLBACK_MESSAGE") or None,
+            max_request_timeout_ms=int(_env("MAX_REQUEST_TIMEOUT_MS", "600000")),
+            warmup=_env("WARMUP", "0") in ("1", "true"),
+            deep_health=_env("DEEP_HEALTH", "0") in ("1", "true"),
+            compression=_env("COMPRESSION", "0") in ("1", "true"),
+            min_compress_size=int(_env("MIN_COMPRESS_SIZE", "1024")),
+        )
@@ -0,0 +1,28 @@
+from __future__ import annotations
+
+import uvicorn
+from minisgl.utils import init_logger
+
+from .api_server import create_gateway_app
+from .config import GatewayConfig
+
+logger = init_logger(__name__, "Gateway")
+
+
+def launch_gateway(config: GatewayConfig | None = None) -> None:
+    """Run the HTTP gateway in front of one or more minisgl API servers."""
+    if config is None:
+        config = GatewayConfig.from_env()
+    # a default pool is optional when every model routes through a dedicated
+    # per-model pool, so either source of workers makes the config valid
+    if not config.workers and not config.model_workers:
+        raise ValueError(
+            "No workers configured "
+            "(set MINISGL_GATEWAY_WORKERS or MINISGL_GATEWAY_MODEL_WORKERS)"
+        )
+    logger.info(f"Gateway serving on {config.host}:{config.port} -> {config.workers}")
+    uvicorn.run(create_gateway_app(config), host=config.host, port=config.port)
+
+
+if __name__ == "__main__":
+    launch_gateway()
@@ -0,0 +1,85 @@
+from __future__ import annotations
+
+import time
+from contextlib import contextmanager
+from dataclasses import dataclass
+from typing import Dict, List
+
+from minisgl.utils import init_logger
+
+logger = init_logger(__name__, "Gateway")
+
+
+@dataclass
+class WorkerState:
+    url: str
+    healthy: bool = True
+    draining: bool = False
+    inflight: int = 0
+
+    @property
+    def available(self) -> bool:
+        return self.healthy and not self.draining
+
+    def describe(self) -> Dict:
+        return {
+            "url": self.url,
+            "healthy": self.healthy,
+            "draining": self.draining,
+            "inflight": self.inflight,
+        }
+
+
+class WorkerPool:
+    def __init__(self, urls: List[str]) -> None:
+        self.workers = [WorkerState(url=url) for url in urls]
+        # exponential moving average of request completion time, used to
+        # estimate how long a rejected client should back off
+        self.avg_completion_ms: float = 0.0
+
+    def get(self, url: str) -> WorkerState | None:
+        url = url.rstrip("/")
+        for worker in self.workers:
+            if worker.url == url:
+                return worker
+        return None
+
+    def select(self) -> WorkerState | None:
+        """Pick the available worker with the fewest in-flight requests."""
+        candidates = [w for w in self.workers if w.available]
+        if not candidates:
+            return None
+        return min(candidates, key=lambda w: w.inflight)
+
+    def set_draining(self, url: str, draining: bool) -> bool:
+        worker = self.get(url)
+        if worker is None:
+            return False
+        worker.draining = draining
+        logger.info("Worker %s is now %s", worker.url, "draining" if draining else "enabled")
+        return True
+
+    @property
+    def total_inflight(self) -> int:
+        return sum(w.inflight for w in self.workers)
+
+    @contextmanager
+    def track(self, worker: WorkerState):
+        worker.inflight += 1
+        start = time.monotonic()
+        try:
+            yield worker
+        finally:
+            worker.inflight -= 1
+            elapsed_ms = (time.monotonic() - start) * 1000.0
+            if self.avg_completion_ms == 0.0:
+                self.avg_completion_ms = elapsed_ms
+            else:
+                self.avg_completion_ms = 0.9 * self.avg_completion_ms + 0.1 * elapsed_ms
+
+    def estimated_wait_ms(self, max_inflight: int) -> float:
+        """Back-off hint for a rejected request, from queue depth and the
+        average completion time spread across the pool."""
+        queued = max(self.total_inflight - max_inflight + 1, 1)
+        per_slot = self.avg_completion_ms or 1000.0  # cold start: assume 1s
+        return per_slot * queued / max(len(self.workers), 1)
@@ -2,8 +2,9 @@ from __future__ import annotations
 
 import enum
 from abc import ABC, abstractmethod
+from contextlib import contextmanager
 from dataclasses import dataclass
-from typing import NamedTuple, Tuple
+from typing import Iterator, NamedTuple, Tuple
 
 import torch
 
@@ -72,6 +73,24 @@ class BaseCacheManager(ABC):
             indices (torch.Tensor): The indices of the longest-matched prefix in the cache.
         """
 
+    def match_prefix_except_last(
+        self, input_ids: torch.Tensor
+    ) -> Tuple[BaseCacheHandle, torch.Tensor]:
+        """
+        Match all but the last token of `input_ids`.
+
+        Prefill must not consume a cached copy of the token whose output it
+        needs, so schedulers match against the truncated prompt; this exposes
+        that slice convention once instead of at every call site.
+
+        Args:
+            input_ids (torch.Tensor): The input ids to match. Shape: (seq_len,), seq_len >= 1.
+        Returns:
+            Same as `match_prefix` on `input_ids[:-1]`.
+        """
+        assert len(input_ids) > 0, "Input length must be greater than 0."
+        return self.match_prefix(input_ids[:-1])
+
     @abstractmethod
     def lock_handle(self, handle: BaseCacheHandle, unlock: bool = False) -> None:
         """
@@ -86,6 +105,21 @@ class BaseCacheManager(ABC):
             unlock (bool): Whether to unlock the handle. Defaults to False.
         """
 
+    @contextmanager
+    def lock_scoped(self, handle: BaseCacheHandle) -> Iterator[BaseCacheHandle]:
+        """
+        Lock a handle for the duration of a `with` block.
+
+        The unlock runs on every exit path, so an early return or exception
+        cannot leak the lock and permanently inflate the protected size the
+        way a missed manual `lock_handle(handle, unlock=True)` would.
+        """
+        self.lock_handle(handle)
+        try:
+            yield handle
+        finally:
+            self.lock_handle(handle, unlock=True)
+
     @abstractmethod
     def insert_prefix(self, input_ids: torch.Tensor, indices: torch.Tensor) -> int:
         """
@@ -100,6 +134,19 @@ class BaseCacheManager(ABC):
                  inserted, so the caller should free these indices.
         """
 
+    @abstractmethod
+    def is_handle_live(self, handle: BaseCacheHandle) -> bool:
+        """
+        Check whether the cache still holds the prefix behind a handle.
+        An unlocked handle may be detached by a later evict; callers holding
+        stale handles must validate them before trusting their indices.
+
+        Args:
+            handle (BaseCacheHandle): The cache handle to validate.
+        Returns:
+            bool: True if the handle's prefix is still in the cache.
+        """
+
     @abstractmethod
     def evict(self, size: int) -> torch.Tensor:
         """
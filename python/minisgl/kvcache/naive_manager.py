@@ -28,6 +28,10 @@ class NaiveCacheManager(BaseCacheManager):
         assert len(indices) == len(input_ids)
         return len(indices)
 
+    def is_handle_live(self, handle: BaseCacheHandle) -> bool:
+        _ = handle  # unused: naive handles never cache anything
+        return True
+
     def evict(self, size: int) -> torch.Tensor:
         if size == 0:
             return self.empty_tensor
@@ -2,24 +2,94 @@ from __future__ import annotations
 
 import heapq
 import time
+from collections import deque
 from dataclasses import dataclass
-from typing import Dict, List, Tuple
+from typing import Any, Dict, List, NamedTuple, Tuple
 
 import torch
 
 from .base import BaseCacheHandle, BaseCacheManager, SizeInfo
 
 
+class LeafInfo(NamedTuple):
+    """One evictable leaf, as seen by a caller-supplied eviction policy."""
+
+    node_id: int
+    timestamp: int
+    length: int
+    first_token: int
+
+
+class CacheEvent(NamedTuple):
+    """One entry of the optional cache event log, for post-mortem replay."""
+
+    op: str  # insert / match / lock / unlock / evict
+    length: int
+    node_id: int
+
+
+class DenseChildren:
+    """
+    A vector-indexed drop-in for the root's children dict.
+
+    The root often fans out to thousands of distinct first tokens, so when the
+    first-token vocab is dense and bounded, indexing by token id avoids hashing
+    on every walk at the cost of O(vocab_size) memory.
+    """
+
+    def __init__(self, vocab_size: int) -> None:
+        self.vocab_size = vocab_size
+        self._slots: List[RadixTreeNode | None] = [None] * vocab_size
+        self._size = 0
+
+    def __contains__(self, token_id: int) -> bool:
+        return self._slots[token_id] is not None
+
+    def __getitem__(self, token_id: int) -> RadixTreeNode:
+        node = self._slots[token_id]
+        assert node is not None
+        return node
+
+    def __setitem__(self, token_id: int, node: RadixTreeNode) -> None:
+        if self._slots[token_id] is None:
+            self._size += 1
+        self._slots[token_id] = node
+
+    def __delitem__(self, token_id: int) -> None:
+        assert self._slots[token_id] is not None
+        self._slots[token_id] = None
+        self._size -= 1
+
+    def __len__(self) -> int:
+        return self._size
+
+    def values(self) -> List[RadixTreeNode]:
+        return [node for node in self._slots if node is not None]
+
+
 class RadixTreeNode:
     counter: int = 0
+    seq_counter: int = 0
 
-    def __init__(self, tic: int | None = None) -> None:
+    def __init__(self, tic: int | None = None, uuid: int | None = None) -> None:
         self.children: Dict[int, RadixTreeNode] = {}
         self._parent: RadixTreeNode | None = None
         self.ref_count: int = 0
-        self.uuid = RadixTreeNode.counter
-        RadixTreeNode.counter += 1
+        # an explicit uuid comes from a manager recycling an evicted id;
+        # otherwise ids are drawn from the global counter
+        if uuid is not None:
+            self.uuid = uuid
+        else:
+            self.uuid = RadixTreeNode.counter
+            RadixTreeNode.counter += 1
+        # monotonic insertion order, used only as the eviction tiebreak; kept
+        # separate from uuid so id reuse (e.g. a future free list) cannot
+        # perturb the ordering
+        self.insert_seq = RadixTreeNode.seq_counter
+        RadixTreeNode.seq_counter += 1
         self.timestamp = tic or time.monotonic_ns()
+        # opaque per-node user metadata (e.g. image hashes for multimodal serving)
+        self.metadata: Any = None
 
         # these fields should be updated later
         self._key: torch.Tensor
@@ -69,6 +139,7 @@ class RadixTreeNode:
         new_node.set_key_value(self._key[:pos], self._value[:pos])
         new_node.set_parent(parent)
         new_node.ref_count = self.ref_count
+        new_node.metadata = self.metadata
 
         self.set_key_value(self._key[pos:], self._value[pos:])
         self.set_parent(new_node)
@@ -76,16 +147,40 @@ class RadixTreeNode:
         return new_node
 
     def __lt__(self, other: RadixTreeNode) -> bool:
-        return self.timestamp < other.timestamp
+        # equal timestamps (coarse clocks, shared batch ticks) fall back to
+        # insertion order so eviction is deterministic
+        return (self.timestamp, self.insert_seq) < (other.timestamp, other.insert_seq)
 
 
-@dataclass(frozen=True)
+@dataclass(frozen=True, eq=False)
 class RadixCacheHandle(BaseCacheHandle):
     node: RadixTreeNode
 
+    @property
+    def metadata(self) -> Any:
+        """User metadata attached to the deepest matched node."""
+        return self.node.metadata
+
+    # handles are interchangeable iff they point at the same node, so equality
+    # is node identity; this lets callers dedup handles in sets/dicts
+    def __eq__(self, other: object) -> bool:
+        if not isinstance(other, RadixCacheHandle):
+            return NotImplemented
+        return self.node is other.node
+
+    def __hash__(self) -> int:
+        return hash(id(self.node))
+
 
 class RadixCacheManager(BaseCacheManager):
-    def __init__(self, device: torch.device):
+    def __init__(
+        self,
+        device: torch.device,
+        min_split_alignment: int = 1,
+        event_log_size: int = 0,
+        recycle_node_ids: bool = False,
+    ):
+        assert min_split_alignment >= 1
         self.device = device
         self.empty_tensor = torch.empty(0, dtype=torch.int32, device=device)
         super().__init__()
@@ -93,10 +188,53 @@ class RadixCacheManager(BaseCacheManager):
         self.root_node.ref_count = 1  # root is always protected
         self.evictable_size = 0
         self.protected_size = 0
+        # for paged caches, splits must align to block boundaries or KV indices
+        # desync; 1 (the default) keeps the exact-match behavior
+        self.min_split_alignment = min_split_alignment
+        # ring buffer of recent cache events; None (size 0) disables recording
+        self._events: deque[CacheEvent] | None = (
+            deque(maxlen=event_log_size) if event_log_size > 0 else None
+        )
+        # cumulative prefill tokens answered from the cache, for ROI reporting
+        self._cached_tokens_served = 0
+        # hand evicted node ids back for reuse, so ids stay bounded on
+        # long-lived heavy-churn servers; safe because the eviction tiebreak
+        # runs on insert_seq, not the (possibly recycled) uuid. The free list
+        # is per manager, so one tree never consumes ids evicted from another.
+        self.recycle_node_ids = recycle_node_ids
+        self._free_node_ids: List[int] = []
+
+    def _new_node(self, tic: int | None = None) -> RadixTreeNode:
+        if self.recycle_node_ids and self._free_node_ids:
+            return RadixTreeNode(tic, uuid=self._free_node_ids.pop())
+        return RadixTreeNode(tic)
+
+    def _record(self, op: str, length: int, node: RadixTreeNode) -> None:
+        if self._events is not None:
+            self._events.append(CacheEvent(op, length, node.uuid))
+
+    def total_cached_tokens_served(self) -> int:
+        """Sum of cached_len over every match so far, i.e. prefill work saved."""
+        return self._cached_tokens_served
+
+    def recent_events(self, n: int) -> List[CacheEvent]:
+        """The last `n` recorded events, oldest first. Empty when disabled."""
+        if self._events is None:
+            return []
+        return list(self._events)[-n:]
+
+    @classmethod
+    def with_vocab_size(cls, device: torch.device, vocab_size: int, **kwargs) -> RadixCacheManager:
+        """Create a manager whose root children are vector-indexed by token id."""
+        assert vocab_size > 0
+        manager = cls(device, **kwargs)
+        manager.root_node.children = DenseChildren(vocab_size)  # type: ignore[assignment]
+        return manager
 
     def lock_handle(self, handle: BaseCacheHandle, unlock: bool = False) -> None:
         assert isinstance(handle, RadixCacheHandle)
         node = handle.node
+        self._record("unlock" if unlock else "lock", handle.cached_len, node)
         if unlock:
             while not node.is_root():
                 node.ref_count -= 1
@@ -113,30 +251,206 @@ class RadixCacheManager(BaseCacheManager):
                 node.ref_count += 1
                 node = node.parent
 
+    def _validate_ids(self, input_ids: torch.Tensor) -> None:
+        if len(input_ids) == 0:
+            return
+        # negative ids cannot be real tokens; storing them as edge keys would
+        # only hide the caller bug, so reject them up front
+        if bool((input_ids < 0).any().item()):
+            raise ValueError("input_ids must not contain negative token ids")
+        # a dense root indexes children by token id, so an out-of-vocab id is
+        # the same caller bug and must not surface as a bare IndexError
+        children = self.root_node.children
+        if isinstance(children, DenseChildren) and bool(
+            (input_ids >= children.vocab_size).any().item()
+        ):
+            raise ValueError(
+                f"input_ids must be below the dense root's vocab_size ({children.vocab_size})"
+            )
+
     def match_prefix(self, input_ids: torch.Tensor) -> Tuple[RadixCacheHandle, torch.Tensor]:
-        node, prefix_len = self._walk(input_ids)
+        self._validate_ids(input_ids)
+        node, prefix_len = self._walk(input_ids, align=self.min_split_alignment)
+        # round down so callers never see a partial block
+        prefix_len -= prefix_len % self.min_split_alignment
         if prefix_len == 0:
-            assert node.is_root() and node is self.root_node and prefix_len == 0
-            return RadixCacheHandle(prefix_len, node), self.empty_tensor
+            self._record("match", 0, self.root_node)
+            return RadixCacheHandle(prefix_len, self.root_node), self.empty_tensor
+        self._record("match", prefix_len, node)
+        self._cached_tokens_served += prefix_len
         value_list: List[torch.Tensor] = []
         matched_node = node
         while not node.is_root():
             value_list.append(node.value)
             node = node.parent
         value_list.reverse()
-        return RadixCacheHandle(prefix_len, matched_node), torch.cat(value_list)
+        indices = torch.cat(value_list)
+        if len(indices) > prefix_len:  # unaligned full-node boundary
+            indices = indices[:prefix_len]
+        return RadixCacheHandle(prefix_len, matched_node), indices
+
+    def match_prefix_with_depth(
+        self, input_ids: torch.Tensor
+    ) -> Tuple[RadixCacheHandle, torch.Tensor, int]:
+        """
+        `match_prefix`, plus the number of radix nodes the match traversed.
+        Depth is a fragmentation signal: a long chain of short nodes backing a
+        short match means many splits piled up along a shared prefix, and the
+        tree would benefit from compaction.
+        """
+        handle, indices = self.match_prefix(input_ids)
+        depth = 0
+        node = handle.node
+        while not node.is_root():
+            depth += 1
+            node = node.parent
+        return handle, indices, depth
+
+    def match_prefix_peek(self, input_ids: torch.Tensor) -> int:
+        """
+        The matched prefix length of `input_ids` without any side effects: no
+        timestamp refresh, no node splits, no event or hit accounting. Meant
+        for admission dry-runs and diagnostics, where a real `match_prefix`
+        would artificially keep cold prefixes alive. The length is uncut, so
+        it may exceed what `match_prefix` returns under split alignment.
+        """
+        self._validate_ids(input_ids)
+        prefix_len = 0
+        node = self.root_node
+        while prefix_len < len(input_ids):
+            this_id = int(input_ids[prefix_len].item())
+            if this_id not in node.children:
+                break
+            node = node.children[this_id]
+            match_len = node.get_match_len(input_ids[prefix_len:])
+            prefix_len += match_len
+            if match_len != node.length:
+                break
+        return prefix_len
 
-    def insert_prefix(self, input_ids: torch.Tensor, indices: torch.Tensor) -> int:
-        node, prefix_len = self._walk(input_ids)
+    def insert_prefix(
+        self, input_ids: torch.Tensor, indices: torch.Tensor, metadata: Any = None
+    ) -> int:
+        self._validate_ids(input_ids)
+        if len(input_ids) != len(indices):
+            raise ValueError(
+                f"input_ids and indices must have the same length,"
+                f" got {len(input_ids)} and {len(indices)}"
+            )
+        node, prefix_len = self._walk(input_ids, align=self.min_split_alignment)
         assert prefix_len <= len(input_ids)
         if prefix_len < len(input_ids):
-            new_node = RadixTreeNode()
+            if int(input_ids[prefix_len].item()) in node.children:
+                # the suffix diverges mid-block inside an existing child;
+                # attaching it would need the mid-block split we forbid, so
+                # the divergent suffix is simply not cached
+                return prefix_len
+            new_node = self._new_node()
             new_node.set_key_value(input_ids[prefix_len:], indices[prefix_len:].clone())
             new_node.set_parent(node)
+            new_node.metadata = metadata
             self.evictable_size += new_node.length
+            self._record("insert", new_node.length, new_node)
         return prefix_len
 
-    def _walk(self, input_ids: torch.Tensor) -> Tuple[RadixTreeNode, int]:
+    def insert_or_get(
+        self, input_ids: torch.Tensor, indices: torch.Tensor, metadata: Any = None
+    ) -> Tuple[int, torch.Tensor]:
+        """
+        Insert like `insert_prefix`, but also report which KV indices actually
+        back the prefix afterwards: the cache's existing indices for the
+        already-present part and the caller's for the newly inserted remainder.
+        Requests racing to insert identical prefixes (n>1 siblings, duplicate
+        prompts) can then drop their duplicate slots instead of leaking them.
+        Returns `(already_present_len, backing_indices)`.
+        """
+        self._validate_ids(input_ids)
+        if len(input_ids) != len(indices):
+            raise ValueError(
+                f"input_ids and indices must have the same length,"
+                f" got {len(input_ids)} and {len(indices)}"
+            )
+        node, prefix_len = self._walk(input_ids, align=self.min_split_alignment)
+        if prefix_len < len(input_ids) and int(input_ids[prefix_len].item()) not in node.children:
+            # as in insert_prefix, a mid-block divergence is not cached
+            new_node = self._new_node()
+            new_node.set_key_value(input_ids[prefix_len:], indices[prefix_len:].clone())
+            new_node.set_parent(node)
+            new_node.metadata = metadata
+            self.evictable_size += new_node.length
+            self._record("insert", new_node.length, new_node)
+        if prefix_len == 0:
+            return 0, indices
+        value_list: List[torch.Tensor] = []
+        while not node.is_root():
+            value_list.append(node.value)
+            node = node.parent
+        value_list.reverse()
+        existing = torch.cat(value_list)
+        assert len(existing) == prefix_len
+        return prefix_len, torch.cat([existing, indices[prefix_len:]])
+
+    def insert_from_handle(
+        self,
+        handle: RadixCacheHandle,
+        remaining_ids: torch.Tensor,
+        remaining_indices: torch.Tensor,
+    ) -> None:
+        """
+        Append a continuation directly under a previously matched node,
+        skipping the second tree walk of the match-then-insert pattern.
+
+        Raises:
+            ValueError: If the handle is stale, the continuation is empty or
+                mismatched in length, or it overlaps an existing branch (in
+                which case the caller should fall back to `insert_prefix`).
+        """
+        if not self.is_handle_live(handle):
+            raise ValueError("Stale handle: its prefix is no longer in the cache")
+        if len(remaining_ids) == 0:
+            raise ValueError("Continuation must not be empty")
+        if len(remaining_ids) != len(remaining_indices):
+            raise ValueError("remaining_ids and remaining_indices must have the same length")
+        node = handle.node
+        if int(remaining_ids[0].item()) in node.children:
+            raise ValueError("Continuation overlaps an existing branch, use insert_prefix")
+        new_node = self._new_node()
+        new_node.set_key_value(remaining_ids, remaining_indices.clone())
+        new_node.set_parent(node)
+        self.evictable_size += new_node.length
+
+    def preload(
+        self, input_ids: torch.Tensor, indices: torch.Tensor, metadata: Any = None
+    ) -> RadixCacheHandle:
+        """
+        Insert a prefix and immediately lock it, for warming the cache with a
+        known system prompt at startup. The returned handle stays protected
+        until the caller unlocks it via `lock_handle(handle, unlock=True)`.
+        """
+        self.insert_prefix(input_ids, indices, metadata)
+        # build the handle from a plain walk instead of match_prefix: preloaded
+        # tokens were never served to a request, so they must not count towards
+        # total_cached_tokens_served or show up as a match event
+        node, prefix_len = self._walk(input_ids, align=self.min_split_alignment)
+        prefix_len -= prefix_len % self.min_split_alignment
+        handle = RadixCacheHandle(prefix_len, node if prefix_len > 0 else self.root_node)
+        self.lock_handle(handle)
+        return handle
+
+    def is_handle_live(self, handle: BaseCacheHandle) -> bool:
+        assert isinstance(handle, RadixCacheHandle)
+        # eviction unlinks a node from its parent but leaves the node intact,
+        # so liveness means every hop up to the root is still linked
+        node = handle.node
+        while not node.is_root():
+            parent = node.parent
+            first_id = int(node._key[0].item())
+            if first_id not in parent.children or parent.children[first_id] is not node:
+                return False
+            node = parent
+        return node is self.root_node
+
+    def _walk(self, input_ids: torch.Tensor, align: int = 1) -> Tuple[RadixTreeNode, int]:
         prefix_len = 0
         indice_len = len(input_ids)
         node = self.root_node
@@ -155,6 +469,15 @@ class RadixCacheManager(BaseCacheManager):
 
             # need to split the node if not fully matched
             if match_len != node.length:
+                if align > 1:
+                    # only split at an aligned boundary; if the aligned boundary
+                    # falls at or before this node's start, do not split at all
+                    aligned_len = prefix_len - prefix_len % align
+                    base_len = prefix_len - match_len
+                    if aligned_len <= base_len:
+                        return node.parent, base_len
+                    node = node._split_at(aligned_len - base_len)
+                    return node, aligned_len
                 node = node._split_at(match_len)
                 return node, prefix_len
 
@@ -184,6 +507,9 @@ class RadixCacheManager(BaseCacheManager):
             evicted_size += node.length
             evicted_indices.append(node.value)
             self.evictable_size -= node.length
+            self._record("evict", node.length, node)
+            if self.recycle_node_ids:
+                self._free_node_ids.append(node.uuid)
             parent = node.parent
             del parent.children[int(node._key[0].item())]
             # NOTE: root is always protected, so won't be evicted
@@ -192,6 +518,83 @@ class RadixCacheManager(BaseCacheManager):
 
         return torch.cat(evicted_indices)
 
+    def evict_sorted(self, size: int) -> torch.Tensor:
+        """
+        Like `evict`, but with the freed indices sorted ascending. Heap-pop
+        order varies run to run; allocators that coalesce adjacent free slots
+        want a deterministic, merge-friendly free list instead.
+        """
+        return self.evict(size).sort().values
+
+    def evict_until_free(self, target_free: int, current_free: int) -> torch.Tensor:
+        """
+        Evict just enough to bring the free size up to `target_free`, given the
+        caller already has `current_free` slots. A satisfied target is a no-op;
+        the eviction stops at node boundaries, so slightly more than the
+        shortfall may be freed.
+
+        Raises:
+            RuntimeError: If even evicting everything cannot reach the target.
+        """
+        needed = max(0, target_free - current_free)
+        if needed == 0:
+            return self.empty_tensor
+        if needed > self.evictable_size:
+            raise RuntimeError(
+                f"Cannot reach {target_free} free tokens:"
+                f" {current_free} free + {self.evictable_size} evictable is not enough"
+            )
+        return self.evict(needed)
+
+    def clear_evictable(self) -> torch.Tensor:
+        """
+        Evict every unprotected node in one pass and return all freed indices,
+        e.g. for a full cache flush on model reload. Locked prefixes (and the
+        root) are left intact; afterwards `evictable_size` is 0.
+        """
+        return self.evict(self.evictable_size)
+
+    def evictable_leaves(self) -> List[LeafInfo]:
+        """
+        Snapshot the currently evictable leaves for bespoke eviction policies:
+        a caller picks its victims from these and frees them via `evict_nodes`,
+        keeping the policy outside the manager while the mechanism stays here.
+        """
+        return [
+            LeafInfo(node.uuid, node.timestamp, node.length, int(node._key[0].item()))
+            for node in self._collect_leave_nodes_for_evict()
+        ]
+
+    def evict_nodes(self, node_ids: List[int]) -> torch.Tensor:
+        """
+        Evict exactly the given evictable leaves and return their indices.
+
+        Raises:
+            ValueError: If any id is unknown, locked, or not currently a leaf
+                (ids must come from a fresh `evictable_leaves` snapshot).
+        """
+        # validate the full id set against the leaf snapshot before touching
+        # anything, so a stale id cannot leave a half-evicted tree behind
+        wanted = set(node_ids)
+        victims: List[RadixTreeNode] = []
+        for node in self._collect_leave_nodes_for_evict():
+            if node.uuid in wanted:
+                wanted.remove(node.uuid)
+                victims.append(node)
+        if wanted:
+            raise ValueError(f"Unknown or non-evictable node ids: {sorted(wanted)}")
+        evicted_indices: List[torch.Tensor] = []
+        for node in victims:
+            evicted_indices.append(node.value)
+            self.evictable_size -= node.length
+            self._record("evict", node.length, node)
+            if self.recycle_node_ids:
+                self._free_node_ids.append(node.uuid)
+            del node.parent.children[int(node._key[0].item())]
+        if not evicted_indices:
+            return self.empty_tensor
+        return torch.cat(evicted_indices)
+
     def _collect_leave_nodes_for_evict(self) -> List[RadixTreeNode]:
         nodes: List[RadixTreeNode] = [self.root_node]
         leave_nodes: List[RadixTreeNode] = []
@@ -207,6 +610,59 @@ class RadixCacheManager(BaseCacheManager):
 
         return leave_nodes
 
+    def top_prefixes(self, k: int) -> List[Tuple[List[int], int]]:
+        """
+        Return the `k` longest root-to-leaf paths as (token_ids, total_length),
+        longest first. Diagnostic only (e.g. for investigating low hit rates);
+        this walks the whole tree and is not meant for the hot path.
+        """
+        stack = [self.root_node]
+        leaves: List[RadixTreeNode] = []
+        while stack:
+            node = stack.pop()
+            if node.is_leaf():
+                if not node.is_root():
+                    leaves.append(node)
+            else:
+                stack.extend(node.children.values())
+
+        paths: List[Tuple[List[int], int]] = []
+        for leaf in leaves:
+            keys: List[torch.Tensor] = []
+            node = leaf
+            while not node.is_root():
+                keys.append(node._key)
+                node = node.parent
+            keys.reverse()
+            ids: List[int] = torch.cat(keys).tolist()
+            paths.append((ids, len(ids)))
+        paths.sort(key=lambda path: path[1], reverse=True)
+        return paths[:k]
+
+    def _compute_sizes(self) -> SizeInfo:
+        """Recompute the size sums from node ref_counts, ignoring the fields."""
+        evictable = protected = 0
+        stack = list(self.root_node.children.values())
+        while stack:
+            node = stack.pop()
+            if node.ref_count == 0:
+                evictable += node.length
+            else:
+                protected += node.length
+            stack.extend(node.children.values())
+        return SizeInfo(evictable_size=evictable, protected_size=protected)
+
+    def recompute_sizes(self) -> SizeInfo:
+        """
+        Recompute `evictable_size`/`protected_size` from scratch and repair the
+        incremental accounting, e.g. after restoring a tree whose counters are
+        out of sync. Returns the recomputed sizes.
+        """
+        info = self._compute_sizes()
+        self.evictable_size = info.evictable_size
+        self.protected_size = info.protected_size
+        return info
+
     def reset(self) -> None:
         raise NotImplementedError("RadixManager.reset is not implemented")
 
@@ -218,4 +674,7 @@ class RadixCacheManager(BaseCacheManager):
         )
 
     def check_integrity(self) -> None:
-        pass
+        info = self._compute_sizes()
+        assert info == self.size_info, (
+            f"Size accounting desynced: tree says {info}, counters say {self.size_info}"
+        )
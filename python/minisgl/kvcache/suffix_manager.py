@@ -0,0 +1,65 @@
+from __future__ import annotations
+
+from typing import Any, NamedTuple
+
+import torch
+
+from .base import SizeInfo
+from .radix_manager import RadixCacheHandle, RadixCacheManager
+
+
+class SuffixCacheMatch(NamedTuple):
+    """Result of `match_suffix`: the matched trailing tokens of a sequence."""
+
+    matched_len: int
+    # KV indices of the matched suffix, in forward (sequence) order
+    indices: torch.Tensor
+    handle: RadixCacheHandle
+
+
+def _flip(tensor: torch.Tensor) -> torch.Tensor:
+    return torch.flip(tensor, dims=[0])
+
+
+class SuffixCacheManager:
+    """
+    A radix cache over reversed token sequences, so common *suffixes* share
+    nodes. Useful for workloads with fixed trailing tokens (e.g. constrained
+    generation with a shared suffix); all node, split and eviction machinery is
+    the underlying `RadixCacheManager` with the keys reversed at the boundary.
+    """
+
+    def __init__(self, device: torch.device, **kwargs):
+        self._inner = RadixCacheManager(device, **kwargs)
+
+    def insert_suffix(
+        self, input_ids: torch.Tensor, indices: torch.Tensor, metadata: Any = None
+    ) -> int:
+        """Insert a sequence keyed by its suffix; returns the already-cached length."""
+        return self._inner.insert_prefix(_flip(input_ids), _flip(indices), metadata)
+
+    def match_suffix(self, input_ids: torch.Tensor) -> SuffixCacheMatch:
+        """Match the longest cached suffix of `input_ids`."""
+        handle, reversed_indices = self._inner.match_prefix(_flip(input_ids))
+        return SuffixCacheMatch(handle.cached_len, _flip(reversed_indices), handle)
+
+    def lock_handle(self, handle: RadixCacheHandle, unlock: bool = False) -> None:
+        self._inner.lock_handle(handle, unlock)
+
+    def evict(self, size: int) -> torch.Tensor:
+        return self._inner.evict(size)
+
+    @property
+    def evictable_size(self) -> int:
+        return self._inner.evictable_size
+
+    @property
+    def protected_size(self) -> int:
+        return self._inner.protected_size
+
+    @property
+    def size_info(self) -> SizeInfo:
+        return self._inner.size_info
+
+    def check_integrity(self) -> None:
+        self._inner.check_integrity()
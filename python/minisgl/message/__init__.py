@@ -1,8 +1,9 @@
-from .backend import BaseBackendMsg, BatchBackendMsg, ExitMsg, UserMsg
+from .backend import AbortMsg, BaseBackendMsg, BatchBackendMsg, ExitMsg, UserMsg
 from .frontend import BaseFrontendMsg, BatchFrontendMsg, UserReply
 from .tokenizer import BaseTokenizerMsg, BatchTokenizerMsg, DetokenizeMsg, TokenizeMsg
 
 __all__ = [
+    "AbortMsg",
     "BaseBackendMsg",
     "BatchBackendMsg",
     "ExitMsg",
@@ -5,8 +5,12 @@ from typing import Dict, List
 
 from minisgl.core import SamplingParams
 
+# NOTE: imported so that the tokenizer-side decoder can resolve forwarded aborts
+from .backend import AbortMsg
 from .utils import deserialize_type, serialize_type
 
+_ = AbortMsg  # re-exported for decoder resolution
+
 
 @dataclass
 class BaseTokenizerMsg:
@@ -29,6 +33,9 @@ class DetokenizeMsg(BaseTokenizerMsg):
     uid: int
     next_token: int
     finished: bool
+    # drop any existing decode state for this uid first, guaranteeing a fresh
+    # stream when a uid is reused (e.g. after a lost finished message)
+    reset: bool = False
 
 
 @dataclass
@@ -36,8 +43,6 @@ class TokenizeMsg(BaseTokenizerMsg):
     uid: int
     text: str | List[Dict[str, str]]
     sampling_params: SamplingParams
-
-
-@dataclass
-class AbortMsg(BaseTokenizerMsg):
-    uid: int
+    # inline template overriding the tokenizer's default chat template for
+    # this request's message rendering (e.g. a "raw" template for some clients)
+    chat_template: str | None = None
@@ -1,6 +1,6 @@
 from __future__ import annotations
 
-from typing import TYPE_CHECKING
+from typing import TYPE_CHECKING, List
 
 import torch
 from minisgl.kvcache import BaseCacheHandle, create_cache_manager
@@ -22,9 +22,8 @@ class CacheManager:
             self._free_slots = torch.cat([self._free_slots, indices])
 
     def match_req(self, req: PendingReq):
-        input_len = req.input_len
-        assert input_len > 0, "Input length must be greater than 0."
-        return self.manager.match_prefix(req.input_ids[: input_len - 1])
+        assert req.input_len > 0, "Input length must be greater than 0."
+        return self.manager.match_prefix_except_last(req.input_ids)
 
     @property
     def available_size(self) -> int:
@@ -61,6 +60,28 @@ class CacheManager:
         self._free(indices[old_handle.cached_len : in_cache_len])
         self.unlock(old_handle)
 
+    def estimate_kv_growth(self, pending: List[PendingReq]) -> int:
+        """
+        Predict the total KV tokens a pending batch will consume:
+        `(input_len - cached_len) + output_len` per request, with cached_len
+        taken from a side-effect-free peek where the manager supports one (no
+        timestamp refresh, node splits or hit accounting, so repeated planning
+        passes cannot keep cold prefixes alive). Nothing is locked or
+        allocated, so this is safe to call for capacity planning before
+        admission.
+        """
+        peek = getattr(self.manager, "match_prefix_peek", None)
+        total = 0
+        for req in pending:
+            if peek is not None:
+                # like match_req, the last token is never served from cache
+                cached_len = peek(req.input_ids[:-1])
+            else:
+                handle, _ = self.match_req(req)
+                cached_len = handle.cached_len
+            total += (req.input_len - cached_len) + req.output_len
+        return total
+
     def check_integrity(self) -> None:
         self.manager.check_integrity()
         if len(self._free_slots) + self.manager.size_info.total_size != self.num_pages:
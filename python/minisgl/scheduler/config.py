@@ -16,6 +16,8 @@ class SchedulerConfig(EngineConfig):
     max_extend_tokens: int = 8192
     cache_type: str = "radix"
     offline_mode: bool = False
+    # decode steps required between prefill steps; 0 always admits prefill
+    prefill_decode_ratio: int = 0
 
     # networking config
     _unique_suffix: str = field(default_factory=_get_pid_suffix)
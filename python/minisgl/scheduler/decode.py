@@ -1,14 +1,23 @@
 from __future__ import annotations
 
 from dataclasses import dataclass, field
-from typing import Iterable, Set
+from typing import Iterable, List, NamedTuple, Set
 
 from minisgl.core import Batch, Req
 
 
+class StepResult(NamedTuple):
+    """What one continuous-batching step reports back to the runner."""
+
+    batch: Batch | None
+    newly_finished: List[int]  # uids that left the running set this step
+    phase: str
+
+
 @dataclass
 class DecodeManager:
     running_reqs: Set[Req] = field(default_factory=set)
+    _newly_finished: List[int] = field(default_factory=list, init=False)
 
     def filter_reqs(self, reqs: Iterable[Req]) -> None:
         self.running_reqs = {req for req in self.running_reqs.union(reqs) if req.can_decode}
@@ -16,6 +25,26 @@ class DecodeManager:
     def remove_req(self, req: Req) -> None:
         self.running_reqs.discard(req)
 
+    def mark_finished(self, uid: int) -> Req | None:
+        """
+        Take a request out of the running set once sampling detects its
+        termination (EOS or max_tokens). The removed request is returned so
+        the caller can free its table slot and cache handle; unknown uids
+        (e.g. an abort racing with completion) return None.
+        """
+        for req in self.running_reqs:
+            if req.uid == uid:
+                self.running_reqs.discard(req)
+                self._newly_finished.append(uid)
+                return req
+        return None
+
+    def step(self) -> StepResult:
+        """Schedule the next decode batch plus the uids finished since last step."""
+        batch = self.schedule_next_batch()
+        newly_finished, self._newly_finished = self._newly_finished, []
+        return StepResult(batch, newly_finished, batch.phase if batch else "idle")
+
     @property
     def inflight_tokens(self) -> int:
         return sum(req.remain_len for req in self.running_reqs)
@@ -0,0 +1,103 @@
+from __future__ import annotations
+
+from dataclasses import dataclass
+from typing import Any, Dict
+
+import msgpack
+import numpy as np
+import torch
+
+
+def _pack_tensor(tensor: torch.Tensor) -> Dict[str, Any]:
+    assert tensor.is_cpu and tensor.dim() == 1, "we can only pack 1D cpu tensor"
+    return {"buffer": tensor.numpy().tobytes(), "dtype": str(tensor.dtype)}
+
+
+def _unpack_tensor(data: Dict[str, Any]) -> torch.Tensor:
+    np_dtype = getattr(np, data["dtype"].replace("torch.", ""))
+    return torch.from_numpy(np.frombuffer(data["buffer"], dtype=np_dtype).copy())
+
+
+@dataclass
+class SchedulerPlan:
+    """
+    A compact description of one scheduled step, suitable for shipping across
+    the process boundary to a model runner.
+
+    All per-request fields are 1D int32 cpu tensors of the same length.
+    """
+
+    phase: str  # "prefill" or "decode"
+    uids: torch.Tensor
+    table_idxs: torch.Tensor
+    cached_lens: torch.Tensor
+    device_lens: torch.Tensor
+
+    def __post_init__(self) -> None:
+        assert self.phase in ("prefill", "decode")
+        assert len(self.uids) == len(self.table_idxs) == len(self.cached_lens)
+        assert len(self.uids) == len(self.device_lens)
+
+    @property
+    def size(self) -> int:
+        return len(self.uids)
+
+    def _encode(self) -> Dict[str, Any]:
+        return {
+            "phase": self.phase,
+            "uids": _pack_tensor(self.uids),
+            "table_idxs": _pack_tensor(self.table_idxs),
+            "cached_lens": _pack_tensor(self.cached_lens),
+            "device_lens": _pack_tensor(self.device_lens),
+        }
+
+    @classmethod
+    def _decode(cls, data: Dict[str, Any]) -> SchedulerPlan:
+        return cls(
+            phase=data["phase"],
+            uids=_unpack_tensor(data["uids"]),
+            table_idxs=_unpack_tensor(data["table_idxs"]),
+            cached_lens=_unpack_tensor(data["cached_lens"]),
+            device_lens=_unpack_tensor(data["device_lens"]),
+        )
+
+    def to_bytes(self) -> bytes:
+        # NOTE: raw tensor buffers go through msgpack bin type, avoiding the
+        # per-element overhead of JSON in the per-step hot path.
+        return msgpack.packb(self._encode(), use_bin_type=True)
+
+    @classmethod
+    def from_bytes(cls, raw: bytes) -> SchedulerPlan:
+        return cls._decode(msgpack.unpackb(raw, raw=False))
+
+
+@dataclass
+class ExecPlan:
+    """A `SchedulerPlan` plus the flattened metadata buffers needed for execution."""
+
+    plan: SchedulerPlan
+    input_ids: torch.Tensor
+    positions: torch.Tensor
+    out_loc: torch.Tensor
+
+    def __post_init__(self) -> None:
+        assert len(self.input_ids) == len(self.positions) == len(self.out_loc)
+
+    def to_bytes(self) -> bytes:
+        data = {
+            "plan": self.plan._encode(),
+            "input_ids": _pack_tensor(self.input_ids),
+            "positions": _pack_tensor(self.positions),
+            "out_loc": _pack_tensor(self.out_loc),
+        }
+        return msgpack.packb(data, use_bin_type=True)
+
+    @classmethod
+    def from_bytes(cls, raw: bytes) -> ExecPlan:
+        data = msgpack.unpackb(raw, raw=False)
+        return cls(
+            plan=SchedulerPlan._decode(data["plan"]),
+            input_ids=_unpack_tensor(data["input_ids"]),
+            positions=_unpack_tensor(data["positions"]),
+            out_loc=_unpack_tensor(data["out_loc"]),
+        )
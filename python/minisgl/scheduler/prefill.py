@@ -35,6 +35,9 @@ class PrefillAdder:
     reserved_size: int
     cache_manager: CacheManager
     table_manager: TableManager
+    # caps a single request's chunk even when the budget is ample, so one long
+    # prefill cannot starve decode; the default is effectively unbounded
+    max_chunk_size: int = 1 << 62
 
     def _try_allocate_one(self, req: PendingReq) -> Tuple[BaseCacheHandle, int] | None:
         if self.table_manager.available_size == 0:
@@ -67,13 +70,21 @@ class PrefillAdder:
         cache_handle: BaseCacheHandle,
         table_idx: int,
         cached_len: int,
+        is_continuation: bool = False,
     ) -> Req:
         remain_len = pending_req.input_len - cached_len
-        chunk_size = min(self.token_budget, remain_len)
+        chunk_size = min(self.token_budget, self.max_chunk_size, remain_len)
         is_chunked = chunk_size < remain_len
         CLS = ChunkedReq if is_chunked else Req
         self.token_budget -= chunk_size
-        self.reserved_size += remain_len + pending_req.output_len
+        if is_continuation:
+            # the first chunk already reserved the full remainder plus the
+            # output; re-adding them here would double-count the reservation
+            # and wrongly reject admissible requests, so later chunks only
+            # charge their own extend
+            self.reserved_size += chunk_size
+        else:
+            self.reserved_size += remain_len + pending_req.output_len
         # NOTE: update the tokens ids only; new pages will be allocated in the scheduler
         _slice = slice(cached_len, cached_len + chunk_size)
         device_ids = self.table_manager.token_pool[table_idx][_slice]
@@ -98,6 +109,7 @@ class PrefillAdder:
                 cache_handle=chunked_req.cache_handle,
                 table_idx=chunked_req.table_idx,
                 cached_len=chunked_req.cached_len,
+                is_continuation=True,
             )
 
         if resource := self._try_allocate_one(pending_req):
@@ -112,11 +124,43 @@ class PrefillAdder:
         return None
 
 
+@dataclass
+class PrefillGate:
+    """
+    Admission pacing between prefill and decode steps.
+
+    With a positive `prefill_decode_ratio`, a new prefill step is only allowed
+    after that many decode steps since the last prefill, so a burst of incoming
+    requests cannot stall ongoing generations. The default of 0 admits prefill
+    whenever pending requests exist.
+    """
+
+    prefill_decode_ratio: int = 0
+    _decode_steps: int = field(default=0, init=False)
+
+    def allow_prefill(self, decode_runnable: bool) -> bool:
+        # with nothing to decode, holding back prefill would just idle the GPU
+        if self.prefill_decode_ratio <= 0 or not decode_runnable:
+            return True
+        return self._decode_steps >= self.prefill_decode_ratio
+
+    def on_batch(self, phase: str) -> None:
+        if phase == "decode":
+            self._decode_steps += 1
+        else:
+            self._decode_steps = 0
+
+
 @dataclass
 class PrefillManager:
     cache_manager: CacheManager
     table_manager: TableManager
     decode_manager: DecodeManager
+    max_chunk_size: int = 1 << 62
+    # reproducible batching: admit pending requests in uid order instead of
+    # insertion order, so the same pending set always yields the same batches
+    # (golden-trace testing, deterministic replay)
+    deterministic: bool = False
     pending_list: List[PendingReq] = field(default_factory=list)
 
     def add_one_req(self, req: UserMsg) -> None:
@@ -125,6 +169,10 @@ class PrefillManager:
     def schedule_next_batch(self, prefill_budget: int) -> Batch | None:
         if len(self.pending_list) == 0:
             return None
+        if self.deterministic:
+            # fixed tiebreak: in-flight chunked requests keep their head-of-line
+            # priority, everything else is ordered by uid
+            self.pending_list.sort(key=lambda p: (p.chunked_req is None, p.uid))
 
         # estimated offset due to in-flight decode
         adder = PrefillAdder(
@@ -132,6 +180,7 @@ class PrefillManager:
             reserved_size=self.decode_manager.inflight_tokens,
             cache_manager=self.cache_manager,
             table_manager=self.table_manager,
+            max_chunk_size=self.max_chunk_size,
         )
         reqs: List[Req] = []
         chunked_list: List[PendingReq] = []
@@ -149,6 +198,26 @@ class PrefillManager:
         self.pending_list = chunked_list + self.pending_list[len(reqs) :]
         return Batch(reqs=reqs, phase="prefill")
 
+    def schedule_all(self, per_batch_budget: int) -> List[Batch]:
+        """
+        Drain the whole pending queue into consecutive batches for offline
+        inference, where the batches are executed strictly in order. Chunked
+        requests are advanced as if their scheduled chunk already ran (its KV
+        exists by the time the follow-up batch executes), so every chunk lands
+        in a later batch; the loop stops once admission stalls on capacity
+        instead of spinning.
+        """
+        batches: List[Batch] = []
+        while self.pending_list:
+            batch = self.schedule_next_batch(per_batch_budget)
+            if batch is None:
+                break
+            batches.append(batch)
+            for req in batch.reqs:
+                if isinstance(req, ChunkedReq):
+                    req.cached_len = req.device_len
+        return batches
+
     @property
     def runnable(self) -> bool:
         return len(self.pending_list) > 0
@@ -7,6 +7,7 @@ import torch.nn.functional as F
 from minisgl.core import Batch, Req
 from minisgl.env import ENV
 from minisgl.message import (
+    AbortMsg,
     BaseBackendMsg,
     BatchBackendMsg,
     DetokenizeMsg,
@@ -20,8 +21,10 @@ from .cache import CacheManager
 from .config import SchedulerConfig
 from .decode import DecodeManager
 from .io import SchedulerIOMixin
-from .prefill import ChunkedReq, PrefillManager
+from .prefill import ChunkedReq, PrefillGate, PrefillManager
+from .stats import SchedulerStats
 from .table import TableManager
+from .utils import BatchMetadata
 
 if TYPE_CHECKING:
     from minisgl.engine import BatchSamplingArgs, ForwardOutput
@@ -73,35 +76,41 @@ class Scheduler(SchedulerIOMixin):
         self.page_table = self.engine.page_table
         self.token_pool = self.table_manager.token_pool
         self.prefill_budget = config.max_extend_tokens
+        self.prefill_gate = PrefillGate(config.prefill_decode_ratio)
+        self.scheduler_stats = SchedulerStats()
 
     def _process_last_data(
         self, last_data: ForwardData | None, ongoing_data: ForwardData | None
     ) -> None:
-        if last_data is None:
-            return
-        batch, (_, next_tokens_cpu, copy_done) = last_data[0].batch, last_data[1]
-        copy_done.synchronize()
-        reply: List[DetokenizeMsg] = []
-
-        for i, req in enumerate(batch.reqs):
-            if req in self.finished_reqs or isinstance(req, ChunkedReq):
-                continue
-
-            next_token_id = next_tokens_cpu[i]
-            req.append_host(next_token_id.unsqueeze(0))
-            next_token = int(next_token_id.item())
-            finished = not req.can_decode
-            if not req.sampling_params.ignore_eos:
-                finished |= next_token == self.eos_token_id
-            reply.append(DetokenizeMsg(uid=req.uid, next_token=next_token, finished=finished))
-
-            # free resources if the req is finished and not ongoing
-            if finished:
-                self.finished_reqs.add(req)
-                self.decode_manager.remove_req(req)
-                logger.debug_rank0("Request %s is finished", req)
-
-        # free resources for finished but not ongoing reqs
+        if last_data is not None:
+            batch, (_, next_tokens_cpu, copy_done) = last_data[0].batch, last_data[1]
+            copy_done.synchronize()
+            reply: List[DetokenizeMsg] = []
+
+            for i, req in enumerate(batch.reqs):
+                if req in self.finished_reqs or isinstance(req, ChunkedReq):
+                    continue
+
+                next_token_id = next_tokens_cpu[i]
+                req.append_host(next_token_id.unsqueeze(0))
+                next_token = int(next_token_id.item())
+                finished = not req.can_decode
+                if not req.sampling_params.ignore_eos:
+                    finished |= next_token == self.eos_token_id
+                reply.append(DetokenizeMsg(uid=req.uid, next_token=next_token, finished=finished))
+
+                # free resources if the req is finished and not ongoing
+                if finished:
+                    self.finished_reqs.add(req)
+                    self.decode_manager.remove_req(req)
+                    logger.debug_rank0("Request %s is finished", req)
+
+            self.send_result(reply)
+
+        # free resources for finished but not ongoing reqs; this also runs with
+        # no last batch, so an abort arriving while nothing is in flight frees
+        # its table slot and locked handle immediately instead of holding them
+        # until unrelated traffic produces the next batch
         ongoing_reqs = ongoing_data[0].batch.reqs if ongoing_data else []
         for req in self.finished_reqs.difference(ongoing_reqs):
             self.table_manager.free(req.table_idx)
@@ -113,7 +122,6 @@ class Scheduler(SchedulerIOMixin):
 
         # keep only ongoing reqs in the finished set
         self.finished_reqs.intersection_update(ongoing_reqs)
-        self.send_result(reply)
 
     def _process_one_msg(self, msg: BaseBackendMsg) -> None:
         if isinstance(msg, BatchBackendMsg):
@@ -136,10 +144,32 @@ class Scheduler(SchedulerIOMixin):
                     f"Adjust max_tokens to {max_output_len} for request {msg.uid}."
                 )
             self.prefill_manager.add_one_req(msg)
+        elif isinstance(msg, AbortMsg):
+            self._abort_req(msg.uid)
         else:
             logger.error(f"Unknown message type: {type(msg)}")
             raise NotImplementedError
 
+    def _abort_req(self, uid: int) -> None:
+        # not yet scheduled: drop it from the pending queue; a chunked req already
+        # holds a table slot and a locked handle, so route it through the finished
+        # path to free resources once it is no longer in-flight
+        for pending in self.prefill_manager.pending_list:
+            if pending.uid == uid:
+                self.prefill_manager.pending_list.remove(pending)
+                if pending.chunked_req is not None:
+                    self.finished_reqs.add(pending.chunked_req)
+                logger.debug_rank0("Aborted pending request uid=%d", uid)
+                return
+        # running: mark as finished so the normal path frees its resources safely
+        for req in self.decode_manager.running_reqs:
+            if req.uid == uid:
+                self.finished_reqs.add(req)
+                self.decode_manager.remove_req(req)
+                logger.debug_rank0("Aborted running request uid=%d", uid)
+                return
+        logger.debug_rank0("Abort for unknown uid=%d is ignored", uid)
+
     def _prepare_batch(self, batch: Batch) -> ForwardInput:
         needed_size = sum(r.extend_len for r in batch.reqs)
         out_loc = self.cache_manager.allocate(needed_size)
@@ -147,9 +177,16 @@ class Scheduler(SchedulerIOMixin):
         if padding_size := self.engine.graph_runner.pad_batch(batch):
             out_loc = F.pad(out_loc, (0, padding_size), value=self.engine.dummy_page)
         batch.out_loc = out_loc
-        batch.positions = _make_positions(batch, self.device)
-        input_mapping = _make_input_tuple(batch, self.device)
-        write_mapping = _make_write_tuple(batch, self.device)
+        metadata = BatchMetadata.build(batch.padded_reqs, batch.reqs, pin_memory=True)
+        batch.positions = metadata.positions.to(self.device, non_blocking=True)
+        input_mapping = (
+            metadata.input_mapping.to(self.device, non_blocking=True),
+            batch.positions,
+        )
+        write_mapping = (
+            metadata.write_req_mapping.to(self.device, non_blocking=True),
+            metadata.write_mapping.to(self.device, non_blocking=True),
+        )
 
         assert all(r.device_len < self.engine.max_seq_len for r in batch.reqs)
         # NOTE: write out_loc to page_table before `prepare_metadata`
@@ -163,12 +200,21 @@ class Scheduler(SchedulerIOMixin):
         )
 
     def _schedule_next_batch(self) -> ForwardInput | None:
-        # TODO: support other policies: e.g. DECODE first
-        batch = (
-            self.prefill_manager.schedule_next_batch(self.prefill_budget)
-            or self.decode_manager.schedule_next_batch()
-        )
-        return self._prepare_batch(batch) if batch else None
+        if self.prefill_gate.allow_prefill(self.decode_manager.runnable):
+            batch = (
+                self.prefill_manager.schedule_next_batch(self.prefill_budget)
+                or self.decode_manager.schedule_next_batch()
+            )
+        else:
+            batch = (
+                self.decode_manager.schedule_next_batch()
+                or self.prefill_manager.schedule_next_batch(self.prefill_budget)
+            )
+        if batch is None:
+            return None
+        self.prefill_gate.on_batch(batch.phase)
+        self.scheduler_stats.on_batch(batch, len(self.decode_manager.running_reqs))
+        return self._prepare_batch(batch)
 
     def _forward(self, forward_input: ForwardInput) -> ForwardOutput:
         batch, sample_args, input_mapping, output_mapping = forward_input
@@ -180,6 +226,12 @@ class Scheduler(SchedulerIOMixin):
         self.decode_manager.filter_reqs(forward_input.batch.reqs)
         return forward_output
 
+    def stats(self) -> SchedulerStats:
+        return self.scheduler_stats
+
+    def reset_stats(self) -> None:
+        self.scheduler_stats.reset()
+
     def run_when_idle(self) -> None:
         """Called when the scheduler is idle to perform background tasks."""
         logger.info_rank0("Scheduler is idle, waiting for new reqs...")
@@ -239,36 +291,3 @@ class Scheduler(SchedulerIOMixin):
         torch.cuda.synchronize(self.device)
         self.sync_all_ranks()
         self.engine.shutdown()
-
-
-def _make_positions(batch: Batch, device: torch.device) -> torch.Tensor:
-    indices_host = torch.empty(len(batch.out_loc), dtype=torch.int32, pin_memory=True)
-    offset = 0
-    for req in batch.padded_reqs:
-        length = req.extend_len
-        torch.arange(
-            req.cached_len,
-            req.device_len,
-            dtype=torch.int32,
-            out=indices_host[offset : offset + length],
-        )
-        offset += length
-    return indices_host.to(device, non_blocking=True)
-
-
-def _make_input_tuple(batch: Batch, device: torch.device) -> Indice2D:
-    mapping_host = torch.empty(len(batch.out_loc), dtype=torch.int32, pin_memory=True)
-    offset = 0
-    for req in batch.padded_reqs:
-        length = req.extend_len
-        mapping_host[offset : offset + length].fill_(req.table_idx)
-        offset += length
-    return mapping_host.to(device, non_blocking=True), batch.positions
-
-
-def _make_write_tuple(batch: Batch, device: torch.device) -> Indice2D:
-    mapping_list = [req.table_idx for req in batch.reqs]
-    mapping_host = torch.tensor(mapping_list, dtype=torch.int32, pin_memory=True)
-    write_list = [(req.device_len if req.can_decode else -1) for req in batch.reqs]
-    write_host = torch.tensor(write_list, dtype=torch.int32, pin_memory=True)
-    return mapping_host.to(device, non_blocking=True), write_host.to(device, non_blocking=True)
@@ -0,0 +1,47 @@
+from __future__ import annotations
+
+from dataclasses import dataclass, field
+from typing import TYPE_CHECKING
+
+if TYPE_CHECKING:
+    from minisgl.core import Batch
+
+
+@dataclass
+class SchedulerStats:
+    """
+    Accumulates per-step scheduling counters for token-budget tuning:
+    how many steps were prefill vs decode, how many tokens each processed,
+    and the average running-set size observed across steps.
+    """
+
+    prefill_steps: int = 0
+    decode_steps: int = 0
+    prefill_tokens: int = 0
+    decode_tokens: int = 0
+    running_size_sum: int = field(default=0, repr=False)
+
+    def on_batch(self, batch: Batch, running_size: int) -> None:
+        num_tokens = sum(req.extend_len for req in batch.reqs)
+        if batch.phase == "prefill":
+            self.prefill_steps += 1
+            self.prefill_tokens += num_tokens
+        else:
+            self.decode_steps += 1
+            self.decode_tokens += num_tokens
+        self.running_size_sum += running_size
+
+    @property
+    def total_steps(self) -> int:
+        return self.prefill_steps + self.decode_steps
+
+    @property
+    def avg_running_size(self) -> float:
+        return self.running_size_sum / self.total_steps if self.total_steps > 0 else 0.0
+
+    def reset(self) -> None:
+        self.prefill_steps = 0
+        self.decode_steps = 0
+        self.prefill_tokens = 0
+        self.decode_tokens = 0
+        self.running_size_sum = 0
@@ -1,12 +1,13 @@
 from __future__ import annotations
 
+import enum
 from dataclasses import dataclass
-from typing import TYPE_CHECKING, List
+from typing import TYPE_CHECKING, Callable, List, NamedTuple, Tuple
 
 import torch
 
 if TYPE_CHECKING:
-    from minisgl.core import SamplingParams
+    from minisgl.core import Req, SamplingParams
 
     from .prefill import ChunkedReq
 
@@ -17,6 +18,15 @@ class PendingReq:
     input_ids: torch.Tensor
     sampling_params: SamplingParams
     chunked_req: ChunkedReq | None = None
+    # optional per-position KV presence for multi-segment prompts (e.g. cached
+    # text around uncached image-embedding spans); None means only the usual
+    # contiguous prefix may be cached
+    cached_mask: torch.Tensor | None = None
+
+    def __post_init__(self) -> None:
+        if self.cached_mask is not None:
+            assert self.cached_mask.dtype == torch.bool
+            assert len(self.cached_mask) == len(self.input_ids)
 
     @property
     def input_len(self) -> int:
@@ -26,8 +36,300 @@ class PendingReq:
     def output_len(self) -> int:
         return self.sampling_params.max_tokens
 
+    def fully_prefilled(self, device_len: int) -> bool:
+        """Whether `device_len` covers the whole prompt, so decode can start."""
+        return device_len >= self.input_len
+
 
 @dataclass
 class ScheduleResult:
     reqs: List[PendingReq]
     output_indices: List[torch.Tensor]
+
+
+class BatchMetadata(NamedTuple):
+    """The four host-side metadata vectors of one scheduled batch."""
+
+    positions: torch.Tensor  # rotary position of every extended token
+    input_mapping: torch.Tensor  # page-table row of every extended token
+    write_req_mapping: torch.Tensor  # page-table row of every request
+    write_mapping: torch.Tensor  # token-pool write slot, -1 for non-decoding reqs
+
+    @classmethod
+    def build(
+        cls, padded_reqs: List[Req], write_reqs: List[Req], pin_memory: bool = False
+    ) -> BatchMetadata:
+        """
+        Bundle the per-batch metadata construction in one call: `padded_reqs`
+        (including any graph-padding dummies) drive the token-level vectors,
+        `write_reqs` the request-level write vectors.
+        """
+        total_len = sum(req.extend_len for req in padded_reqs)
+        positions = torch.empty(total_len, dtype=torch.int32, pin_memory=pin_memory)
+        input_mapping = torch.empty(total_len, dtype=torch.int32, pin_memory=pin_memory)
+        offset = 0
+        for req in padded_reqs:
+            length = req.extend_len
+            torch.arange(
+                req.cached_len,
+                req.device_len,
+                dtype=torch.int32,
+                out=positions[offset : offset + length],
+            )
+            input_mapping[offset : offset + length].fill_(req.table_idx)
+            offset += length
+
+        write_req_mapping = torch.tensor(
+            [req.table_idx for req in write_reqs], dtype=torch.int32, pin_memory=pin_memory
+        )
+        write_mapping = torch.tensor(
+            [(req.device_len if req.can_decode else -1) for req in write_reqs],
+            dtype=torch.int32,
+            pin_memory=pin_memory,
+        )
+        return cls(positions, input_mapping, write_req_mapping, write_mapping)
+
+
+def make_decode_positions(reqs: List[Req]) -> torch.Tensor:
+    """
+    Compute the rotary position of each decoding request in a pure decode step.
+
+    Each decoding request contributes exactly one position, its current
+    `cached_len..device_len` extend range of length 1. Chunked and otherwise
+    non-decoding requests are skipped.
+    """
+    positions = [req.device_len - 1 for req in reqs if req.can_decode]
+    return torch.tensor(positions, dtype=torch.int32)
+
+
+def extend_ranges(reqs: List[Req]) -> List[Tuple[int, int]]:
+    """
+    The half-open `[cached_len, device_len)` extend range per request, for
+    callers (e.g. segment-aware attention masks) that want explicit ranges
+    rather than the flattened positions vector.
+    """
+    return [(req.cached_len, req.device_len) for req in reqs]
+
+
+def make_positions_with(
+    reqs: List[Req], position_fn: Callable[[Req, int], int]
+) -> torch.Tensor:
+    """
+    The flattened per-token positions under a custom scheme: `position_fn` maps
+    each index of a request's `cached_len..device_len` extend range to its
+    rotary position. Models with non-default RoPE (position interpolation,
+    packing) reuse the iteration logic this way; the identity closure
+    `lambda req, idx: idx` reproduces the default positions.
+    """
+    values = [
+        position_fn(req, idx)
+        for req in reqs
+        for idx in range(req.cached_len, req.device_len)
+    ]
+    return torch.tensor(values, dtype=torch.int32)
+
+
+def plan_chunks(input_len: int, cached_len: int, chunk_size: int) -> List[Tuple[int, int]]:
+    """
+    The half-open device-length ranges each prefill chunk of a prompt will
+    cover, for planning and visualization ahead of execution. A fully cached
+    prompt yields no chunks; the last range carries any remainder.
+    """
+    assert chunk_size >= 1
+    assert 0 <= cached_len <= input_len
+    return [
+        (start, min(start + chunk_size, input_len))
+        for start in range(cached_len, input_len, chunk_size)
+    ]
+
+
+def decode_write_slots(reqs: List[Req]) -> List[Tuple[int, int]]:
+    """
+    The `(table_idx, device_len)` KV write slot of each decoding request: the
+    new token's KV lands at index `device_len` within the request's table row.
+    Unlike the write-tuple vectors, non-decoding (e.g. chunked) requests are
+    simply absent instead of marked with a -1 sentinel.
+    """
+    return [(req.table_idx, req.device_len) for req in reqs if req.can_decode]
+
+
+def make_masked_positions(input_len: int, cached_mask: torch.Tensor | None) -> torch.Tensor:
+    """
+    Rotary positions of the entries that still need KV for a multi-segment
+    prompt. Unlike the contiguous `cached_len..device_len` range, cached spans
+    may appear anywhere (cached prefix, uncached middle, cached tail); a
+    missing mask means nothing is cached yet.
+    """
+    if cached_mask is None:
+        return torch.arange(input_len, dtype=torch.int32)
+    assert len(cached_mask) == input_len
+    return torch.nonzero(~cached_mask).view(-1).to(torch.int32)
+
+
+def make_masked_input_tuple(
+    table_idx: int, input_len: int, cached_mask: torch.Tensor | None
+) -> Tuple[torch.Tensor, torch.Tensor]:
+    """
+    The (req_mapping, positions) scatter pair for one multi-segment request:
+    one page-table row entry per uncached position. Feeding this to
+    `page_table[mapping] = out_loc` fills exactly the KV gaps.
+    """
+    positions = make_masked_positions(input_len, cached_mask)
+    mapping = torch.full((len(positions),), table_idx, dtype=torch.int32)
+    return mapping, positions
+
+
+def make_spec_write_tuple(
+    reqs: List[Req], accepted_counts: List[int]
+) -> Tuple[torch.Tensor, torch.Tensor]:
+    """
+    Compute the (req_mapping, write_positions) pair for speculative verification.
+
+    Each request writes `accepted_counts[i]` consecutive KV slots starting at
+    its current `device_len`; fully rejected drafts contribute nothing. With
+    all counts equal to 1 this degenerates to the single-token decode write.
+    """
+    mapping: List[int] = []
+    positions: List[int] = []
+    for req, accepted in zip(reqs, accepted_counts, strict=True):
+        mapping.extend([req.table_idx] * accepted)
+        positions.extend(range(req.device_len, req.device_len + accepted))
+    return (
+        torch.tensor(mapping, dtype=torch.int32),
+        torch.tensor(positions, dtype=torch.int32),
+    )
+
+
+def batch_token_cost(reqs: List[Req]) -> int:
+    """
+    The token budget a scheduled batch consumes: the sum of `extend_len`, i.e.
+    the tokens this step actually computes. Schedulers can assert after the
+    fact that an admitted batch stayed within its budget.
+    """
+    return sum(req.extend_len for req in reqs)
+
+
+class BatchStats(NamedTuple):
+    """One-shot aggregate statistics of a scheduled batch, for step logging."""
+
+    extend_tokens: int  # total tokens extended this step
+    decode_tokens: int  # extend tokens contributed by decoding requests
+    num_chunked: int
+    num_decoding: int
+    max_device_len: int
+    cached_tokens: int  # sum of cached_len, i.e. cache reuse
+
+
+def batch_stats(reqs: List[Req]) -> BatchStats:
+    """Compute `BatchStats` in one pass, replacing ad-hoc per-field sums."""
+    from .prefill import ChunkedReq
+
+    return BatchStats(
+        extend_tokens=batch_token_cost(reqs),
+        decode_tokens=sum(req.extend_len for req in reqs if req.can_decode),
+        num_chunked=sum(isinstance(req, ChunkedReq) for req in reqs),
+        num_decoding=sum(req.can_decode for req in reqs),
+        max_device_len=max((req.device_len for req in reqs), default=0),
+        cached_tokens=sum(req.cached_len for req in reqs),
+    )
+
+
+class VictimPolicy(enum.Enum):
+    LongestRemaining = enum.auto()  # sacrifice the requests furthest from done
+    Newest = enum.auto()  # LIFO: the latest admissions lose their slot first
+
+
+def select_preemption_victims(
+    running: List[Req], need: int, policy: VictimPolicy
+) -> List[int]:
+    """
+    Pick which running requests to preempt when decode runs out of KV budget.
+
+    Returns the indices (in preemption order) of the minimal set whose freed
+    `remain_len` reservations sum to at least `need` tokens, ranked by the
+    policy: `LongestRemaining` preempts the most remaining work first, so the
+    least progress is thrown away per freed token; `Newest` walks the running
+    set back to front, so older requests keep their slots.
+
+    Raises:
+        RuntimeError: If preempting every request still frees less than `need`.
+    """
+    if need <= 0:
+        return []
+    if policy is VictimPolicy.LongestRemaining:
+        order = sorted(range(len(running)), key=lambda i: running[i].remain_len, reverse=True)
+    else:
+        order = list(range(len(running) - 1, -1, -1))
+    victims: List[int] = []
+    freed = 0
+    for idx in order:
+        victims.append(idx)
+        freed += running[idx].remain_len
+        if freed >= need:
+            return victims
+    raise RuntimeError(f"Cannot free {need} tokens: only {freed} are preemptible")
+
+
+def merge_batches(decode: List[Req], prefill: List[Req]) -> List[Req]:
+    """
+    Combine a decode batch with a prefill batch into one executor call,
+    decode first. Both sides must target disjoint page-table rows.
+
+    Raises:
+        ValueError: If a table_idx appears in both batches.
+    """
+    decode_idxs = {req.table_idx for req in decode}
+    for req in prefill:
+        if req.table_idx in decode_idxs:
+            raise ValueError(f"table_idx {req.table_idx} appears in both batches")
+    return decode + prefill
+
+
+def pad_device_lens(reqs: List[Req], multiple_of: int) -> List[int]:
+    """
+    Per-request device length rounded up to a multiple of `multiple_of`, for
+    attention kernels that require uniformly aligned sequence lengths.
+    """
+    assert multiple_of >= 1
+    return [-(-req.device_len // multiple_of) * multiple_of for req in reqs]
+
+
+def max_padded_device_len(reqs: List[Req], multiple_of: int) -> int:
+    """The padded length the whole batch must be allocated to."""
+    return max(pad_device_lens(reqs, multiple_of), default=0)
+
+
+def pad_reqs_to(reqs: List[Req], target_batch: int, dummy_table_idx: int) -> List[Req]:
+    """
+    Pad a batch with zero-extend dummy requests up to `target_batch`, for
+    fixed-shape (CUDA-graph style) execution. The dummies contribute no
+    extended tokens, so the metadata builders are unchanged by them.
+    """
+    from minisgl.core import Req
+
+    padded = list(reqs)
+    for _ in range(target_batch - len(reqs)):
+        dummy = Req(
+            input_ids=torch.tensor([0], dtype=torch.int32),
+            table_idx=dummy_table_idx,
+            cached_len=0,
+            output_len=1,
+            uid=-1,
+            sampling_params=None,  # type: ignore
+            cache_handle=None,  # type: ignore
+        )
+        dummy.cached_len = dummy.device_len  # extend_len 0
+        padded.append(dummy)
+    return padded
+
+
+def partition_batch(reqs: List[Req]) -> Tuple[List[Req], List[Req]]:
+    """
+    Split a mixed batch into its (decoding, prefilling) subsets in one pass,
+    classified by `can_decode`, preserving the batch order within each subset.
+    """
+    decoding: List[Req] = []
+    prefilling: List[Req] = []
+    for req in reqs:
+        (decoding if req.can_decode else prefilling).append(req)
+    return decoding, prefilling
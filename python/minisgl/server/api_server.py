@@ -6,7 +6,7 @@ import sys
 import time
 from contextlib import asynccontextmanager
 from dataclasses import dataclass, field
-from typing import Callable, Dict, List, Literal, Tuple
+from typing import Callable, Dict, List, Literal, Set, Tuple
 
 import uvicorn
 from fastapi import FastAPI
@@ -14,6 +14,7 @@ from fastapi.responses import StreamingResponse
 from minisgl.core import SamplingParams
 from minisgl.env import ENV
 from minisgl.message import (
+    AbortMsg,
     BaseFrontendMsg,
     BaseTokenizerMsg,
     BatchFrontendMsg,
@@ -105,6 +106,11 @@ class FrontendManager:
     initialized: bool = False
     ack_map: Dict[int, List[UserReply]] = field(default_factory=dict)
     event_map: Dict[int, asyncio.Event] = field(default_factory=dict)
+    # uids whose stream saw its finished ack; lets the disconnect abort hook
+    # tell a completed stream from a dropped client without racing generator
+    # cleanup (the consumer breaks out of wait_for_ack, so its own cleanup
+    # may never run)
+    finished_uids: Set[int] = field(default_factory=set)
 
     def new_user(self) -> int:
         uid = self.uid_counter
@@ -153,6 +159,7 @@ class FrontendManager:
             yield f"data: {ack.incremental_output}\n".encode()
             if ack.finished:
                 break
+        self.finished_uids.add(uid)
         yield "data: [DONE]\n".encode()
         logger.debug("Finished streaming response for user %s", uid)
 
@@ -176,6 +183,7 @@ class FrontendManager:
             if ack.finished:
                 break
 
+        self.finished_uids.add(uid)
         # send final finish_reason
         end_chunk = {
             "id": f"cmpl-{uid}",
@@ -187,11 +195,15 @@ class FrontendManager:
         logger.debug("Finished streaming response for user %s", uid)
 
     async def abort_user(self, uid: int):
-        await asyncio.sleep(0.1)
-        if uid in self.ack_map:
-            del self.ack_map[uid]
-        if uid in self.event_map:
-            del self.event_map[uid]
+        if uid in self.finished_uids:
+            self.finished_uids.discard(uid)
+            return  # the stream delivered its finished ack, nothing to abort
+        if uid not in self.ack_map and uid not in self.event_map:
+            return  # already aborted or cleaned up
+        self.ack_map.pop(uid, None)
+        self.event_map.pop(uid, None)
+        # tell the backend to stop generation and free the KV cache
+        await self.send_one(AbortMsg(uid=uid))
         logger.warning("Aborting request for user %s", uid)
 
     def shutdown(self):
@@ -233,7 +245,7 @@ async def generate(req: GenerateRequest):
     return StreamingResponse(
         state.stream_generate(uid),
         media_type="text/event-stream",
-        background=BackgroundTask(lambda: _abort),
+        background=BackgroundTask(_abort),
     )
 
 
@@ -273,7 +285,7 @@ async def v1_completions(req: OpenAICompletionRequest):
     return StreamingResponse(
         state.stream_chat_completions(uid),
         media_type="text/event-stream",
-        background=BackgroundTask(lambda: _abort),
+        background=BackgroundTask(_abort),
     )
 
 
@@ -310,7 +322,7 @@ async def shell_completion(req: OpenAICompletionRequest):
     return StreamingResponse(
         state.stream_generate(uid),
         media_type="text/event-stream",
-        background=BackgroundTask(lambda: _abort),
+        background=BackgroundTask(_abort),
     )
 
 
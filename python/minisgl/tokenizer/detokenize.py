@@ -1,13 +1,19 @@
 from __future__ import annotations
 
+import re
 from dataclasses import dataclass
-from typing import TYPE_CHECKING, Dict, List
+from typing import TYPE_CHECKING, Dict, List, NamedTuple, Tuple
 
 if TYPE_CHECKING:
     from transformers import LlamaTokenizer
 
 from minisgl.message import DetokenizeMsg
 
+from .tokenize import validate_unique_uids
+
+# byte-fallback tokens look like <0xE4>
+_BYTE_PIECE_RE = re.compile(r"^<0x([0-9A-Fa-f]{2})>$")
+
 # Borrowed from sglang
 
 
@@ -36,7 +42,11 @@ def _is_chinese_char(cp: int):
     return False
 
 
-def find_printable_text(text: str):
+# structural characters that complete a unit of code output
+_CODE_FLUSH_CHARS = "{};)\n"
+
+
+def find_printable_text(text: str, code_mode: bool = False):
     """Returns the longest printable substring of text that contains only entire words."""
     # Borrowed from https://github.com/huggingface/transformers/blob/061580c82c2db1de9139528243e105953793f7a2/src/transformers/generation/streamers.py#L99
 
@@ -51,8 +61,34 @@ def find_printable_text(text: str):
         return text[:-1]
     # Otherwise, prints until the last space char (simple heuristic to avoid printing incomplete words,
     # which may change with the subsequent token -- there are probably smarter ways to do this!)
+    # In code mode, closing braces/parens and semicolons also complete a unit,
+    # so structured output is not held back until the next space.
     else:
-        return text[: text.rfind(" ") + 1]
+        boundaries = _CODE_FLUSH_CHARS + " " if code_mode else " "
+        return text[: max(text.rfind(c) for c in boundaries) + 1]
+
+
+def _is_complete_utf8(data: bytes) -> bool:
+    try:
+        data.decode("utf-8")
+        return True
+    except UnicodeDecodeError:
+        return False
+
+
+class DetokenizeOutput(NamedTuple):
+    """One step of `detokenize_full`."""
+
+    text: str  # emitted text for this step
+    span: Tuple[int, int]  # char range of `text` within the cumulative output
+    # the cumulative decode including the held-back tail; everything before
+    # `stable_len_chars` is guaranteed final, the rest is provisional and may
+    # still change (e.g. a U+FFFD that becomes a real char once its bytes land)
+    provisional_text: str
+    stable_len_chars: int
+    # "length" when the manager force-finished the request at max_total_tokens;
+    # None otherwise (normal finishes are decided upstream by the scheduler)
+    finish_reason: str | None = None
 
 
 @dataclass
@@ -62,19 +98,139 @@ class DecodeStatus:
     read_offset: int  # length of read ids
     surr_offset: int  # length of surr ids
     sent_offset: int  # length of sent out string
+    byte_buffer: bytes = b""  # pending byte-fallback tokens of an unfinished char
+    strip_pending: bool = False  # leading-prefix strip not yet applied
 
 
 class DetokenizeManager:
-    def __init__(self, tokenizer: LlamaTokenizer) -> None:
+    def __init__(
+        self,
+        tokenizer: LlamaTokenizer,
+        output_len_hint: int | None = None,
+        flush_on_newline: bool = False,
+        cumulative: bool = False,
+        debug_self_check: bool = False,
+        code_mode: bool = False,
+        max_total_tokens: int | None = None,
+        eos_marker: str | None = None,
+        strip_leading: str | None = None,
+    ) -> None:
         # uid -> DecodeStatus
         self.decode_map: Dict[int, DecodeStatus] = {}
         self.tokenizer = tokenizer
         self.eos_token_id = self.tokenizer.eos_token_id
+        # advisory hint of the expected output length per request; CPython lists
+        # and strings cannot reserve capacity, so this must never change outputs
+        self.output_len_hint = output_len_hint
+        # line-oriented consumers (logs, code UIs) prefer complete lines: only
+        # release output up to the last '\n', holding back the partial line
+        self.flush_on_newline = flush_on_newline
+        # emit the full decoded string each step instead of the delta, for
+        # clients that prefer stateless UI updates; offset tracking is kept
+        # unchanged so spans and flushing behave identically
+        self.cumulative = cumulative
+        # re-decode everything from scratch after every step and verify the
+        # emitted prefix against it; O(n^2), for CI and fuzzing only
+        self.debug_self_check = debug_self_check
+        # flush on structural code characters in addition to word boundaries
+        self.code_mode = code_mode
+        # safety backstop against runaway sequences: force-finish a request
+        # with reason "length" once this many tokens were decoded, regardless
+        # of the model's own stopping
+        self.max_total_tokens = max_total_tokens
+        # render the final EOS as a visible marker (e.g. "<EOS>" for debug
+        # transcripts) instead of the default suppression
+        self.eos_marker = eos_marker
+        # drop this prefix from a sequence's first emitted chunk, for chat
+        # templates (continue_final_message) that echo the assistant header;
+        # the prefix must be contained in that first chunk, later chunks are
+        # never touched
+        self.strip_leading = strip_leading
+
+    @classmethod
+    def new_with_hint(cls, tokenizer: LlamaTokenizer, expected_output_len: int) -> DetokenizeManager:
+        assert expected_output_len > 0
+        return cls(tokenizer, output_len_hint=expected_output_len)
+
+    def detokenize_bytes(self, msgs: List[DetokenizeMsg]) -> List[bytes]:
+        """
+        Return the raw decoded byte delta for each step.
+
+        Unlike `detokenize`, this bypasses the U+FFFD holdback entirely: a multibyte
+        character split across byte-fallback tokens is emitted byte by byte, never
+        lossily replaced. Consumers are expected to do their own UTF-8 handling.
+        """
+        results: List[bytes] = []
+        for msg in msgs:
+            if msg.finished and msg.next_token == self.eos_token_id:
+                results.append(b"")
+            else:
+                results.append(self._token_bytes(msg.next_token))
+        return results
+
+    def _token_bytes(self, token_id: int) -> bytes:
+        piece = self.tokenizer.convert_ids_to_tokens(token_id)
+        if match := _BYTE_PIECE_RE.match(piece):
+            return bytes([int(match.group(1), 16)])
+        # NOTE: sentencepiece uses U+2581 as the word-boundary marker
+        return piece.replace("▁", " ").encode("utf-8")
+
+    def _track_byte_fallback(self, s: DecodeStatus, token_id: int) -> None:
+        """
+        Track byte-fallback tokens of an in-progress multibyte char.
+
+        A non-empty buffer means the char is incomplete, so the step must be
+        held back regardless of what the underlying decoder returned for the
+        partial bytes (not every tokenizer maps them to U+FFFD).
+        """
+        piece = self.tokenizer.convert_ids_to_tokens(token_id)
+        if match := _BYTE_PIECE_RE.match(piece):
+            s.byte_buffer += bytes([int(match.group(1), 16)])
+            if _is_complete_utf8(s.byte_buffer):
+                s.byte_buffer = b""
+        else:
+            s.byte_buffer = b""
+
+    def abort_req(self, uid: int) -> None:
+        """Drop any decode state for an aborted request."""
+        self.decode_map.pop(uid, None)
 
     def detokenize(self, msgs: List[DetokenizeMsg]) -> List[str]:
+        return [result.text for result in self.detokenize_full(msgs)]
+
+    def detokenize_with_spans(self, msgs: List[DetokenizeMsg]) -> List[Tuple[str, Tuple[int, int]]]:
+        """
+        Like `detokenize`, but each step additionally reports the
+        `(start_char, end_char)` range its emitted text occupies within the
+        cumulative decoded string. Held-back steps report an empty range; the
+        held-back text is attributed to the step that later flushes it, so
+        previously reported spans never change.
+        """
+        return [(result.text, result.span) for result in self.detokenize_full(msgs)]
+
+    def detokenize_with_stability(self, msgs: List[DetokenizeMsg]) -> List[Tuple[str, int]]:
+        """
+        Return `(provisional_text, stable_len_chars)` per step: the cumulative
+        decode including the held-back tail, and how many of its leading chars
+        are guaranteed not to change. Clients doing find-and-replace on the
+        stream should only touch text before the stable boundary.
+        """
+        return [
+            (result.provisional_text, result.stable_len_chars)
+            for result in self.detokenize_full(msgs)
+        ]
+
+    def detokenize_full(self, msgs: List[DetokenizeMsg]) -> List[DetokenizeOutput]:
+        """The full per-step record; the other detokenize variants project it."""
+        if __debug__ and (dup := validate_unique_uids(msgs)) is not None:
+            raise ValueError(f"Duplicate uid {dup} in detokenize batch")
         read_ids: List[List[int]] = []
         surr_ids: List[List[int]] = []
+        incomplete: List[bool] = []
         for msg in msgs:
+            if msg.reset:
+                # stale state from a reused uid would corrupt the new stream
+                self.decode_map.pop(msg.uid, None)
             if msg.uid not in self.decode_map:
                 self.decode_map[msg.uid] = DecodeStatus(
                     decoded_ids=[],
@@ -82,34 +238,90 @@ class DetokenizeManager:
                     read_offset=0,
                     surr_offset=0,
                     sent_offset=0,
+                    strip_pending=self.strip_leading is not None,
                 )
             s = self.decode_map[msg.uid]
             if not (msg.finished and msg.next_token == self.eos_token_id):
                 s.decoded_ids.append(msg.next_token)
+                self._track_byte_fallback(s, msg.next_token)
             read_ids.append(s.decoded_ids[s.surr_offset :])
             surr_ids.append(s.decoded_ids[s.surr_offset : s.read_offset])
+            incomplete.append(len(s.byte_buffer) > 0)
 
         read_texts = self.tokenizer.batch_decode(read_ids)
         surr_texts = self.tokenizer.batch_decode(surr_ids)
 
-        incremental_strs: List[str] = []
-        for msg, read_str, surr_str in zip(msgs, read_texts, surr_texts, strict=True):
+        results: List[DetokenizeOutput] = []
+        for msg, read_str, surr_str, held in zip(
+            msgs, read_texts, surr_texts, incomplete, strict=True
+        ):
             s = self.decode_map[msg.uid]
+            forced_length = (
+                self.max_total_tokens is not None
+                and not msg.finished
+                and len(s.decoded_ids) >= self.max_total_tokens
+            )
+            finished = msg.finished or forced_length
             new_text = read_str[len(surr_str) :]
+            raw_new_text = new_text
             # Streaming chunk: update the decode status
-            if len(new_text) > 0 and not new_text.endswith("�"):
+            committed = len(new_text) > 0 and not new_text.endswith("�") and not held
+            if committed:
                 output_str = s.decoded_str + new_text
                 s.decoded_str = output_str
                 s.surr_offset = s.read_offset
                 s.read_offset = len(s.decoded_ids)
             else:
-                new_text = find_printable_text(new_text)
+                new_text = find_printable_text(new_text, code_mode=self.code_mode)
                 output_str = s.decoded_str + new_text
 
-            incremental_output = output_str[s.sent_offset :]
-            s.sent_offset = len(output_str)
-            incremental_strs.append(incremental_output)
-            if msg.finished:
+            if self.flush_on_newline and not finished:
+                # release whole lines only; a finished request flushes the rest
+                flush_upto = max(output_str.rfind("\n") + 1, s.sent_offset)
+            else:
+                flush_upto = len(output_str)
+            start_char = s.sent_offset
+            emit_from = 0 if self.cumulative else s.sent_offset
+            output = output_str[emit_from:flush_upto]
+            s.sent_offset = flush_upto
+            if s.strip_pending and output:
+                # only the very first emitted chunk can carry the header
+                if self.strip_leading is not None and output.startswith(self.strip_leading):
+                    output = output[len(self.strip_leading) :]
+                s.strip_pending = False
+            if (
+                self.eos_marker is not None
+                and msg.finished
+                and msg.next_token == self.eos_token_id
+            ):
+                output += self.eos_marker
+                s.sent_offset += len(self.eos_marker)
+            # everything committed to decoded_str is final; a heuristic flush
+            # beyond it (find_printable_text) and the held-back tail are not
+            provisional = s.decoded_str if committed else s.decoded_str + raw_new_text
+            stable_len = min(s.sent_offset, len(s.decoded_str))
+            results.append(
+                DetokenizeOutput(
+                    output,
+                    (start_char, s.sent_offset),
+                    provisional,
+                    stable_len,
+                    finish_reason="length" if forced_length else None,
+                )
+            )
+            if self.debug_self_check:
+                self._self_check(s, output_str)
+            if finished:
                 del self.decode_map[msg.uid]
 
-        return incremental_strs
+        return results
+
+    def _self_check(self, s: DecodeStatus, output_str: str) -> None:
+        """Verify the emitted prefix against a from-scratch decode of all ids."""
+        fresh = self.tokenizer.decode(s.decoded_ids)
+        sent = output_str[: s.sent_offset]
+        if not fresh.startswith(sent):
+            raise RuntimeError(
+                "Detokenize self-check failed: emitted output"
+                f" {sent!r} diverges from the full decode {fresh!r}"
+            )
@@ -5,6 +5,7 @@ from typing import List
 
 import torch
 from minisgl.message import (
+    AbortMsg,
     BaseBackendMsg,
     BaseFrontendMsg,
     BaseTokenizerMsg,
@@ -17,7 +18,7 @@ from minisgl.message import (
     UserReply,
 )
 from minisgl.utils import ZmqPullQueue, ZmqPushQueue, init_logger
-from transformers import AutoTokenizer, LlamaTokenizer
+from transformers import LlamaTokenizer
 
 
 def _unwrap_msg(msg: BaseTokenizerMsg) -> List[BaseTokenizerMsg]:
@@ -42,11 +43,11 @@ def tokenize_worker(
     send_frontend = ZmqPushQueue(frontend_addr, create=False, encoder=BaseFrontendMsg.encoder)
     recv_listener = ZmqPullQueue(addr, create=create, decoder=BatchTokenizerMsg.decoder)
     assert local_bs > 0
-    tokenizer: LlamaTokenizer = AutoTokenizer.from_pretrained(tokenizer_path, use_fast=True)
-    logger = init_logger(__name__, f"tokenizer_{tokenizer_id}")
-
     from .detokenize import DetokenizeManager
-    from .tokenize import TokenizeManager
+    from .tokenize import TokenizeManager, load_tokenizer
+
+    tokenizer: LlamaTokenizer = load_tokenizer(tokenizer_path)
+    logger = init_logger(__name__, f"tokenizer_{tokenizer_id}")
 
     tokenize_manager = TokenizeManager(tokenizer)
     detokenize_manager = DetokenizeManager(tokenizer)
@@ -64,7 +65,13 @@ def tokenize_worker(
 
             detokenize_msg = [m for m in pending_msg if isinstance(m, DetokenizeMsg)]
             tokenize_msg = [m for m in pending_msg if isinstance(m, TokenizeMsg)]
-            assert len(detokenize_msg) + len(tokenize_msg) == len(pending_msg)
+            abort_msg = [m for m in pending_msg if isinstance(m, AbortMsg)]
+            assert len(detokenize_msg) + len(tokenize_msg) + len(abort_msg) == len(pending_msg)
+
+            # forward aborts to the scheduler and drop any local decode state
+            for msg in abort_msg:
+                detokenize_manager.abort_req(msg.uid)
+                send_backend.put(msg)
             if len(detokenize_msg) > 0:
                 replies = detokenize_manager.detokenize(detokenize_msg)
                 batch_output = BatchFrontendMsg(
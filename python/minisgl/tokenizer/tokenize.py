@@ -1,33 +1,195 @@
 from __future__ import annotations
 
-from typing import TYPE_CHECKING, List
+import os
+from typing import TYPE_CHECKING, Dict, Iterable, Iterator, List
 
 import torch
-from minisgl.message import TokenizeMsg
+from minisgl.message import DetokenizeMsg, TokenizeMsg
 
 if TYPE_CHECKING:
     from transformers import LlamaTokenizer
 
 
+def validate_unique_uids(msgs: Iterable[TokenizeMsg | DetokenizeMsg]) -> int | None:
+    """
+    Return the first uid appearing more than once in a batch, or None.
+
+    Both tokenize managers key per-request state by uid; a caller bug that
+    batches duplicate uids silently corrupts that state, so the managers run
+    this check at the top of their batch entry points under `__debug__`.
+    """
+    seen: set[int] = set()
+    for msg in msgs:
+        if msg.uid in seen:
+            return msg.uid
+        seen.add(msg.uid)
+    return None
+
+
+class TokenizerError(ValueError):
+    """Base error for invalid tokenizer inputs."""
+
+
+class EmptyMessagesError(TokenizerError):
+    """Raised when a chat request carries an empty message list."""
+
+
+class EmptyTextError(TokenizerError):
+    """Raised when an empty text prompt is rejected by configuration."""
+
+
+class TokenIdOverflowError(TokenizerError, OverflowError):
+    """Raised when an encoded token id does not fit the int32 used downstream."""
+
+
+def load_tokenizer(tokenizer_path: str) -> LlamaTokenizer:
+    """
+    Load a tokenizer with meaningful failure types: a missing local path
+    raises FileNotFoundError, any other load failure raises RuntimeError
+    instead of whatever the backend library throws.
+    """
+    from transformers import AutoTokenizer
+
+    # only unambiguously local paths get the existence check; hub repo ids
+    # like "org/model" also contain a separator and must fall through to
+    # from_pretrained
+    is_local = os.path.isabs(tokenizer_path) or tokenizer_path.startswith(("./", "../"))
+    if is_local and not os.path.exists(tokenizer_path):
+        raise FileNotFoundError(f"Tokenizer path does not exist: {tokenizer_path}")
+    try:
+        return AutoTokenizer.from_pretrained(tokenizer_path, use_fast=True)
+    except Exception as e:
+        raise RuntimeError(f"Failed to load tokenizer from {tokenizer_path}") from e
+
+
 class TokenizeManager:
-    def __init__(self, tokenizer: LlamaTokenizer) -> None:
+    def __init__(
+        self,
+        tokenizer: LlamaTokenizer,
+        dedup: bool = False,
+        allow_empty_text: bool = True,
+    ) -> None:
         self.tokenizer = tokenizer
+        # encode identical prompts within a batch only once (common in eval
+        # harnesses with repeated system prompts)
+        self.dedup = dedup
+        self.allow_empty_text = allow_empty_text
+
+    def _render_prompt(self, msg: TokenizeMsg) -> str:
+        if isinstance(msg.text, list):
+            # applying a chat template to zero messages is undefined behavior
+            # depending on the template, so reject it explicitly
+            if len(msg.text) == 0:
+                raise EmptyMessagesError(f"Request {msg.uid} has an empty message list")
+            prompt = self.tokenizer.apply_chat_template(
+                msg.text,
+                tokenize=False,
+                add_generation_prompt=True,
+                # None falls back to the tokenizer's own template
+                chat_template=msg.chat_template,
+            )
+            assert isinstance(prompt, str)
+            return prompt
+        if msg.text == "" and not self.allow_empty_text:
+            raise EmptyTextError(f"Request {msg.uid} has an empty text prompt")
+        return msg.text
+
+    def _encode(self, prompt: str) -> torch.Tensor:
+        input_ids: torch.Tensor = (  # type: ignore
+            self.tokenizer.encode(prompt, return_tensors="pt")
+        )
+        input_ids = input_ids.view(-1)
+        if len(input_ids) > 0 and int(input_ids.max().item()) > torch.iinfo(torch.int32).max:
+            raise TokenIdOverflowError(
+                f"Token id {int(input_ids.max().item())} does not fit in int32"
+            )
+        return input_ids.to(torch.int32)
+
+    def encode_suffix(
+        self,
+        full_text: str,
+        cached_prefix_text: str,
+        cached_prefix_ids: torch.Tensor | None = None,
+        backoff_chars: int = 16,
+    ) -> torch.Tensor:
+        """
+        Encode only the part of `full_text` beyond an already-tokenized prefix.
+
+        Tokenization is not prefix-stable, so a small backoff window before the
+        boundary is re-encoded and compared against the prefix encoding; if the
+        window does not line up (a token merged across the boundary), the whole
+        text is re-encoded as a fallback. Either way the result satisfies
+        `encode(prefix) + encode_suffix(...) == encode(full_text)` or raises.
+        Passing the already-known `cached_prefix_ids` avoids re-encoding the
+        prefix, so the fast path only touches the suffix plus the window.
+
+        Raises:
+            ValueError: If `cached_prefix_text` is not a prefix of `full_text`,
+                or not a token-boundary prefix of the full encoding.
+        """
+        if not full_text.startswith(cached_prefix_text):
+            raise ValueError("cached_prefix_text is not a prefix of full_text")
+        if cached_prefix_text == "":
+            return self._encode(full_text)
+
+        if cached_prefix_ids is None:
+            cached_prefix_ids = self._encode(cached_prefix_text)
+        prefix_ids = cached_prefix_ids
+        window_start = max(0, len(cached_prefix_text) - backoff_chars)
+        if window_start > 0:
+            tail_ids = self._encode(cached_prefix_text[window_start:])
+            window_ids = self._encode(full_text[window_start:])
+            n = len(tail_ids)
+            if (
+                0 < n <= min(len(prefix_ids), len(window_ids))
+                and torch.equal(window_ids[:n], tail_ids)
+                and torch.equal(prefix_ids[-n:], tail_ids)
+            ):
+                return window_ids[n:]
+
+        # the boundary is not locally stable: re-encode everything and validate
+        full_ids = self._encode(full_text)
+        n = len(prefix_ids)
+        if len(full_ids) < n or not torch.equal(full_ids[:n], prefix_ids):
+            raise ValueError(
+                "cached_prefix_text is not a token-boundary prefix of the full encoding"
+            )
+        return full_ids[n:]
+
+    def tokenize_streaming(self, msg: TokenizeMsg, batch_size: int) -> Iterator[torch.Tensor]:
+        """
+        Tokenize one request and yield the token ids in chunks of `batch_size`.
+
+        Encoding itself is not incremental; this chunks the result so callers
+        streaming very large inputs over the wire never have to buffer the
+        whole token vector in one response.
+        """
+        assert batch_size >= 1
+        input_ids = self._encode(self._render_prompt(msg))
+        for start in range(0, len(input_ids), batch_size):
+            yield input_ids[start : start + batch_size]
+
+    def count_tokens_batch(self, texts: List[str]) -> List[int]:
+        """Token counts only (e.g. for quota preflight), skipping id payloads."""
+        if not self.dedup:
+            return [len(self._encode(text)) for text in texts]
+        unique: Dict[str, int] = {}
+        for text in texts:
+            if text not in unique:
+                unique[text] = len(self._encode(text))
+        return [unique[text] for text in texts]
 
     def tokenize(self, msgs: List[TokenizeMsg]) -> List[torch.Tensor]:
-        results: List[torch.Tensor] = []
+        if __debug__ and (dup := validate_unique_uids(msgs)) is not None:
+            raise ValueError(f"Duplicate uid {dup} in tokenize batch")
         # TODO: batch tokenization
-        for msg in msgs:
-            if isinstance(msg.text, list):
-                prompt = self.tokenizer.apply_chat_template(
-                    msg.text,
-                    tokenize=False,
-                    add_generation_prompt=True,
-                )
-                assert isinstance(prompt, str)
-            else:
-                prompt = msg.text
-            input_ids: torch.Tensor = (  # type: ignore
-                self.tokenizer.encode(prompt, return_tensors="pt")
-            )
-            results.append(input_ids.view(-1).to(torch.int32))
-        return results
+        prompts = [self._render_prompt(msg) for msg in msgs]
+        if not self.dedup:
+            return [self._encode(prompt) for prompt in prompts]
+
+        unique: Dict[str, torch.Tensor] = {}
+        for prompt in prompts:
+            if prompt not in unique:
+                unique[prompt] = self._encode(prompt)
+        # fan the unique results back out, preserving per-uid output order
+        return [unique[prompt] for prompt in prompts]
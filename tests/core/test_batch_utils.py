@@ -0,0 +1,425 @@
+from __future__ import annotations
+
+import torch
+from minisgl.core import Batch, Req, SamplingParams
+from minisgl.kvcache.naive_manager import NaiveCacheHandle
+from minisgl.scheduler.prefill import ChunkedReq
+from minisgl.scheduler.stats import SchedulerStats
+from minisgl.scheduler.utils import (
+    BatchMetadata,
+    BatchStats,
+    PendingReq,
+    VictimPolicy,
+    batch_stats,
+    batch_token_cost,
+    decode_write_slots,
+    extend_ranges,
+    make_decode_positions,
+    make_masked_input_tuple,
+    make_masked_positions,
+    make_positions_with,
+    make_spec_write_tuple,
+    max_padded_device_len,
+    merge_batches,
+    pad_device_lens,
+    pad_reqs_to,
+    plan_chunks,
+    partition_batch,
+    select_preemption_victims,
+)
+from minisgl.utils import call_if_main, init_logger
+
+logger = init_logger(__name__)
+
+
+def make_req(
+    uid: int,
+    input_len: int,
+    cached_len: int = 0,
+    output_len: int = 4,
+    chunked: bool = False,
+) -> Req:
+    CLS = ChunkedReq if chunked else Req
+    return CLS(
+        input_ids=torch.zeros(input_len, dtype=torch.int32),
+        table_idx=uid,
+        cached_len=cached_len,
+        output_len=output_len,
+        uid=uid,
+        sampling_params=SamplingParams(max_tokens=output_len),
+        cache_handle=NaiveCacheHandle(0),
+    )
+
+
+@call_if_main()
+def test_make_decode_positions():
+    # decoding requests have an extend range of exactly one token
+    reqs = [make_req(i, input_len) for i, input_len in enumerate([5, 9, 17])]
+    for req in reqs:
+        req.cached_len = req.device_len - 1
+
+    positions = make_decode_positions(reqs)
+    assert positions.tolist() == [4, 8, 16]
+
+    # parity with the generic cached_len..device_len extend range
+    expected = torch.cat(
+        [torch.arange(req.cached_len, req.device_len, dtype=torch.int32) for req in reqs]
+    )
+    assert torch.equal(positions, expected)
+
+    # chunked requests never decode and are skipped
+    reqs.append(make_req(3, 8, chunked=True))
+    assert make_decode_positions(reqs).tolist() == [4, 8, 16]
+
+
+@call_if_main()
+def test_merge_batches():
+    decode = [make_req(0, 5), make_req(1, 9)]
+    prefill = [make_req(2, 12, chunked=True)]
+    merged = merge_batches(decode, prefill)
+    assert [req.table_idx for req in merged] == [0, 1, 2]
+
+    # a shared page-table row is a scheduler bug, not a mergeable batch
+    try:
+        merge_batches(decode, [make_req(1, 4)])
+        raise AssertionError("expected ValueError")
+    except ValueError as e:
+        assert "appears in both batches" in str(e)
+
+
+@call_if_main()
+def test_sampling_presets():
+    from minisgl.message.utils import deserialize_type, serialize_type
+
+    greedy = SamplingParams.greedy(max_tokens=8)
+    assert greedy.is_greedy and greedy.temperature == 0.0 and greedy.max_tokens == 8
+
+    creative = SamplingParams.creative()
+    assert creative.temperature == 0.8 and creative.top_p == 0.95
+    balanced = SamplingParams.balanced()
+    assert balanced.temperature == 0.7 and balanced.top_p == 1.0
+    assert not creative.is_greedy and not balanced.is_greedy
+
+    # presets survive the message-layer round trip
+    restored = deserialize_type({"SamplingParams": SamplingParams}, serialize_type(greedy))
+    assert restored == greedy
+
+
+@call_if_main()
+def test_logprob_params():
+    from minisgl.message.utils import deserialize_type, serialize_type
+
+    # defaults: no logprobs, no prompt echo
+    default = SamplingParams()
+    assert default.logprobs is None and not default.echo
+
+    params = SamplingParams(logprobs=5, echo=True)
+    restored = deserialize_type({"SamplingParams": SamplingParams}, serialize_type(params))
+    assert restored == params
+
+    try:
+        SamplingParams(logprobs=SamplingParams.MAX_LOGPROBS + 1)
+        raise AssertionError("expected AssertionError")
+    except AssertionError as e:
+        assert "logprobs must be in" in str(e)
+
+
+@call_if_main()
+def test_pad_device_lens():
+    reqs = [make_req(i, input_len) for i, input_len in enumerate([5, 8, 9, 17])]
+    assert pad_device_lens(reqs, 8) == [8, 8, 16, 24]
+    assert max_padded_device_len(reqs, 8) == 24
+    # multiple_of=1 keeps the exact lengths
+    assert pad_device_lens(reqs, 1) == [5, 8, 9, 17]
+    assert max_padded_device_len([], 8) == 0
+
+
+@call_if_main()
+def test_batch_validate():
+    good = Batch(reqs=[make_req(0, 5), make_req(1, 8, chunked=True)], phase="prefill")
+    good.validate()
+
+    def expect_invalid(batch: Batch, fragment: str) -> None:
+        try:
+            batch.validate()
+            raise AssertionError(f"expected ValueError about {fragment!r}")
+        except ValueError as e:
+            assert fragment in str(e)
+
+    # two reqs sharing a page-table row
+    duplicated = Batch(reqs=[make_req(0, 5), make_req(0, 7)], phase="prefill")
+    expect_invalid(duplicated, "Duplicate table_idx")
+
+    # inverted lengths after a bad mutation
+    inverted = Batch(reqs=[make_req(0, 5)], phase="decode")
+    inverted.reqs[0].cached_len = 9
+    expect_invalid(inverted, "inverted lengths")
+
+    # device_len beyond the reservation
+    overrun = Batch(reqs=[make_req(0, 5)], phase="decode")
+    overrun.reqs[0].device_len = overrun.reqs[0].max_device_len + 1
+    expect_invalid(overrun, "exceeds its reservation")
+
+
+@call_if_main()
+def test_masked_positions():
+    # cached prefix (0..3), uncached middle (3..6), cached tail (6..8)
+    mask = torch.tensor([True] * 3 + [False] * 3 + [True] * 2)
+    req = PendingReq(
+        uid=0,
+        input_ids=torch.zeros(8, dtype=torch.int32),
+        sampling_params=SamplingParams(max_tokens=4),
+        cached_mask=mask,
+    )
+    positions = make_masked_positions(req.input_len, req.cached_mask)
+    assert positions.tolist() == [3, 4, 5]
+
+    mapping, positions = make_masked_input_tuple(7, req.input_len, req.cached_mask)
+    assert mapping.tolist() == [7, 7, 7]
+    assert positions.tolist() == [3, 4, 5]
+
+    # no mask: everything needs KV
+    assert make_masked_positions(4, None).tolist() == [0, 1, 2, 3]
+
+
+@call_if_main()
+def test_scheduler_stats():
+    stats = SchedulerStats()
+    prefill_reqs = [make_req(0, 8), make_req(1, 6, cached_len=2)]
+    stats.on_batch(Batch(reqs=prefill_reqs, phase="prefill"), running_size=0)
+
+    decode_reqs = [make_req(2, 5), make_req(3, 7)]
+    for req in decode_reqs:
+        req.cached_len = req.device_len - 1  # decode extends by one token
+    for running in (2, 2):
+        stats.on_batch(Batch(reqs=decode_reqs, phase="decode"), running_size=running)
+
+    assert stats.prefill_steps == 1 and stats.decode_steps == 2
+    assert stats.prefill_tokens == 8 + 4
+    assert stats.decode_tokens == 4
+    assert stats.avg_running_size == (0 + 2 + 2) / 3
+
+    stats.reset()
+    assert stats.total_steps == 0 and stats.avg_running_size == 0.0
+
+
+@call_if_main()
+def test_make_spec_write_tuple():
+    reqs = [make_req(i, input_len) for i, input_len in enumerate([5, 9, 17])]
+
+    # all-ones accepted counts degenerate to the decode write mapping
+    mapping, positions = make_spec_write_tuple(reqs, [1, 1, 1])
+    assert mapping.tolist() == [req.table_idx for req in reqs]
+    assert positions.tolist() == [req.device_len for req in reqs]
+
+    # multi-accept writes consecutive slots; rejected drafts write nothing
+    mapping, positions = make_spec_write_tuple(reqs, [3, 0, 2])
+    assert mapping.tolist() == [0, 0, 0, 2, 2]
+    assert positions.tolist() == [5, 6, 7, 17, 18]
+
+
+@call_if_main()
+def test_extend_ranges():
+    decoding = make_req(0, 6)
+    decoding.cached_len = decoding.device_len - 1
+    mixed = [decoding, make_req(1, 9, cached_len=4), make_req(2, 5, chunked=True)]
+
+    ranges = extend_ranges(mixed)
+    assert ranges == [(5, 6), (4, 9), (0, 5)]
+    # flattening the ranges reproduces the positions vector
+    flattened = [pos for start, end in ranges for pos in range(start, end)]
+    assert flattened == BatchMetadata.build(mixed, mixed).positions.tolist()
+
+
+@call_if_main()
+def test_decode_write_slots():
+    decoding = [make_req(0, 5), make_req(1, 9)]
+    chunked = make_req(2, 4, chunked=True)
+    reqs = decoding + [chunked]
+
+    slots = decode_write_slots(reqs)
+    assert slots == [(0, 5), (1, 9)]
+
+    # parity with the non-sentinel entries of the write-tuple vectors
+    metadata = BatchMetadata.build(reqs, reqs)
+    expected = [
+        (int(table_idx), int(slot))
+        for table_idx, slot in zip(metadata.write_req_mapping, metadata.write_mapping)
+        if slot >= 0
+    ]
+    assert slots == expected
+
+
+@call_if_main()
+def test_trim_trailing():
+    eos = torch.tensor([0], dtype=torch.int32)
+
+    # the trailing EOS falls beyond the cached region: only lengths shrink
+    req = make_req(0, 6, cached_len=4, output_len=4)
+    req.input_ids = torch.tensor([1, 2, 3, 4, 5, 0], dtype=torch.int32)
+    assert req.trim_trailing(eos)
+    assert req.input_ids.tolist() == [1, 2, 3, 4, 5]
+    assert req.device_len == 5 and req.cached_len == 4
+    assert req.max_device_len == 5 + 4
+
+    # the trim falls within the cached region: cached_len is pulled back,
+    # always leaving at least one token to prefill
+    req = make_req(1, 6, cached_len=5, output_len=4)
+    req.input_ids = torch.tensor([1, 2, 3, 4, 0, 0], dtype=torch.int32)
+    assert req.trim_trailing(torch.tensor([0, 0], dtype=torch.int32))
+    assert req.cached_len < req.device_len == 4
+
+    # a non-matching tail is left untouched
+    req = make_req(2, 4, cached_len=2)
+    req.input_ids = torch.tensor([1, 2, 3, 4], dtype=torch.int32)
+    assert not req.trim_trailing(eos)
+    assert req.device_len == 4 and req.cached_len == 2
+
+
+@call_if_main()
+def test_pad_reqs_to():
+    reqs = [make_req(i, input_len) for i, input_len in enumerate([5, 9, 17])]
+    padded = pad_reqs_to(reqs, target_batch=8, dummy_table_idx=31)
+    assert len(padded) == 8
+    assert padded[:3] == reqs
+    assert all(req.table_idx == 31 and req.extend_len == 0 for req in padded[3:])
+
+    # the dummies contribute nothing to the metadata vectors
+    metadata = BatchMetadata.build(padded, reqs)
+    unpadded = BatchMetadata.build(reqs, reqs)
+    assert all(torch.equal(lhs, rhs) for lhs, rhs in zip(metadata, unpadded))
+
+    # already large enough: unchanged
+    assert pad_reqs_to(reqs, target_batch=2, dummy_table_idx=31) == reqs
+
+
+@call_if_main()
+def test_batch_metadata():
+    decoding = make_req(0, 6)
+    decoding.cached_len = decoding.device_len - 1
+    prefilling = make_req(1, 4, cached_len=2, chunked=True)
+    reqs = [decoding, prefilling]
+
+    metadata = BatchMetadata.build(reqs, reqs)
+    # bundled output equals the individual per-request builders
+    expected_positions = torch.cat(
+        [torch.arange(req.cached_len, req.device_len, dtype=torch.int32) for req in reqs]
+    )
+    assert torch.equal(metadata.positions, expected_positions)
+    assert metadata.input_mapping.tolist() == [0, 1, 1]
+    assert metadata.write_req_mapping.tolist() == [0, 1]
+    # only decoding requests write a sampled token; chunked prefills write -1
+    assert metadata.write_mapping.tolist() == [decoding.device_len, -1]
+
+    # graph-padding dummies have extend_len 0 and leave the vectors unchanged
+    dummy = make_req(3, 2)
+    dummy.cached_len = dummy.device_len
+    padded = BatchMetadata.build(reqs + [dummy], reqs)
+    assert all(torch.equal(lhs, rhs) for lhs, rhs in zip(padded, metadata))
+
+
+@call_if_main()
+def test_partition_batch():
+    reqs = [
+        make_req(0, 5),
+        make_req(1, 8, chunked=True),
+        make_req(2, 9),
+        make_req(3, 4, chunked=True),
+    ]
+    decoding, prefilling = partition_batch(reqs)
+    assert [req.uid for req in decoding] == [0, 2]
+    assert [req.uid for req in prefilling] == [1, 3]
+    # the partition matches the can_decode classification exactly
+    assert all(req.can_decode for req in decoding)
+    assert not any(req.can_decode for req in prefilling)
+
+
+@call_if_main()
+def test_plan_chunks():
+    # fits in a single chunk
+    assert plan_chunks(input_len=5, cached_len=0, chunk_size=8) == [(0, 5)]
+    # divides exactly
+    assert plan_chunks(input_len=8, cached_len=0, chunk_size=4) == [(0, 4), (4, 8)]
+    # a remainder lands in a shorter final chunk
+    assert plan_chunks(input_len=10, cached_len=0, chunk_size=4) == [(0, 4), (4, 8), (8, 10)]
+    # a cached prefix shifts the first chunk; a fully cached prompt needs none
+    assert plan_chunks(input_len=10, cached_len=3, chunk_size=4) == [(3, 7), (7, 10)]
+    assert plan_chunks(input_len=6, cached_len=6, chunk_size=4) == []
+
+
+@call_if_main()
+def test_make_positions_with():
+    reqs = [make_req(0, 6, cached_len=2), make_req(1, 4)]
+
+    # the identity closure reproduces the default extend-range positions
+    identity = make_positions_with(reqs, lambda req, idx: idx)
+    metadata = BatchMetadata.build(reqs, reqs)
+    assert torch.equal(identity, metadata.positions)
+
+    # a custom scheme (here: doubled positions) takes effect per token
+    doubled = make_positions_with(reqs, lambda req, idx: idx * 2)
+    assert torch.equal(doubled, identity * 2)
+    assert doubled.tolist() == [4, 6, 8, 10, 0, 2, 4, 6]
+
+    assert len(make_positions_with([], lambda req, idx: idx)) == 0
+
+
+@call_if_main()
+def test_batch_stats():
+    decoding = [make_req(0, 5), make_req(1, 9)]
+    for req in decoding:
+        req.cached_len = req.device_len - 1
+    chunked = make_req(2, 12, cached_len=4, chunked=True)
+    stats = batch_stats(decoding + [chunked])
+
+    assert stats == BatchStats(
+        extend_tokens=1 + 1 + 8,
+        decode_tokens=2,
+        num_chunked=1,
+        num_decoding=2,
+        max_device_len=12,
+        cached_tokens=4 + 8 + 4,
+    )
+    # parity with the can_decode classification used by partition_batch
+    assert stats.num_decoding == len(partition_batch(decoding + [chunked])[0])
+
+    assert batch_stats([]) == BatchStats(0, 0, 0, 0, 0, 0)
+
+
+@call_if_main()
+def test_batch_token_cost():
+    decoding = make_req(0, 6)
+    decoding.cached_len = decoding.device_len - 1
+    chunked = make_req(1, 9, cached_len=4, chunked=True)
+    cold = make_req(2, 5)
+    reqs = [decoding, chunked, cold]
+
+    cost = batch_token_cost(reqs)
+    assert cost == 1 + 5 + 5
+    # the cost is exactly the flattened positions length
+    assert cost == len(BatchMetadata.build(reqs, reqs).positions)
+    assert batch_token_cost([]) == 0
+
+
+@call_if_main()
+def test_select_preemption_victims():
+    # fresh requests, so remain_len == output_len: [4, 10, 2, 6]
+    running = [make_req(i, 8, output_len=out) for i, out in enumerate([4, 10, 2, 6])]
+
+    # most remaining work first, stopping as soon as need is covered
+    assert select_preemption_victims(running, 10, VictimPolicy.LongestRemaining) == [1]
+    assert select_preemption_victims(running, 12, VictimPolicy.LongestRemaining) == [1, 3]
+
+    # LIFO walks the running set back to front
+    assert select_preemption_victims(running, 5, VictimPolicy.Newest) == [3]
+    assert select_preemption_victims(running, 9, VictimPolicy.Newest) == [3, 2, 1]
+
+    # nothing needed, nothing preempted
+    assert select_preemption_victims(running, 0, VictimPolicy.Newest) == []
+
+    # more than the whole running set can free
+    try:
+        select_preemption_victims(running, 23, VictimPolicy.LongestRemaining)
+        raise AssertionError("expected RuntimeError")
+    except RuntimeError:
+        pass
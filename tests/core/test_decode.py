@@ -0,0 +1,56 @@
+from __future__ import annotations
+
+import torch
+from minisgl.core import Req, SamplingParams
+from minisgl.kvcache.naive_manager import NaiveCacheHandle
+from minisgl.scheduler.decode import DecodeManager
+from minisgl.scheduler.table import TableManager
+from minisgl.utils import call_if_main, init_logger
+
+logger = init_logger(__name__)
+
+
+def make_decoding_req(uid: int, table_idx: int) -> Req:
+    return Req(
+        input_ids=torch.tensor([1, 2, 3], dtype=torch.int32),
+        table_idx=table_idx,
+        cached_len=2,
+        output_len=8,
+        uid=uid,
+        sampling_params=SamplingParams(max_tokens=8),
+        cache_handle=NaiveCacheHandle(0),
+    )
+
+
+@call_if_main()
+def test_mark_finished():
+    table_manager = TableManager(max_running_reqs=4, page_table=torch.zeros(4, 16, dtype=torch.int32))
+    manager = DecodeManager()
+    reqs = [make_decoding_req(uid, table_manager.allocate()) for uid in range(3)]
+    manager.filter_reqs(reqs)
+
+    result = manager.step()
+    assert result.phase == "decode" and result.newly_finished == []
+    assert {req.uid for req in result.batch.reqs} == {0, 1, 2}
+
+    # the runner detects termination and hands the slot back
+    finished = manager.mark_finished(1)
+    assert finished is reqs[1]
+    table_manager.free(finished.table_idx)
+    assert table_manager.available_size == 2
+
+    # the next step excludes it and reports it exactly once
+    result = manager.step()
+    assert result.newly_finished == [1]
+    assert {req.uid for req in result.batch.reqs} == {0, 2}
+    assert manager.step().newly_finished == []
+
+    # unknown uids (e.g. an abort racing with completion) are a no-op
+    assert manager.mark_finished(99) is None
+
+    # with nothing left running, the step is idle
+    for uid in (0, 2):
+        manager.mark_finished(uid)
+    result = manager.step()
+    assert result.batch is None and result.phase == "idle"
+    assert sorted(result.newly_finished) == [0, 2]
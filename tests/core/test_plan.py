@@ -0,0 +1,60 @@
+from __future__ import annotations
+
+import json
+
+import torch
+from minisgl.scheduler.plan import ExecPlan, SchedulerPlan
+from minisgl.utils import call_if_main, init_logger
+
+logger = init_logger(__name__)
+
+
+def _make_plan(num_reqs: int) -> SchedulerPlan:
+    return SchedulerPlan(
+        phase="decode",
+        uids=torch.arange(num_reqs, dtype=torch.int32),
+        table_idxs=torch.arange(num_reqs, dtype=torch.int32),
+        cached_lens=torch.full((num_reqs,), 128, dtype=torch.int32),
+        device_lens=torch.full((num_reqs,), 129, dtype=torch.int32),
+    )
+
+
+@call_if_main()
+def test_plan_roundtrip():
+    plan = _make_plan(128)
+    restored = SchedulerPlan.from_bytes(plan.to_bytes())
+    assert restored.phase == plan.phase
+    assert torch.equal(restored.uids, plan.uids)
+    assert torch.equal(restored.table_idxs, plan.table_idxs)
+    assert torch.equal(restored.cached_lens, plan.cached_lens)
+    assert torch.equal(restored.device_lens, plan.device_lens)
+
+    exec_plan = ExecPlan(
+        plan=plan,
+        input_ids=torch.arange(128, dtype=torch.int32),
+        positions=torch.arange(128, dtype=torch.int32),
+        out_loc=torch.arange(128, dtype=torch.int32),
+    )
+    restored_exec = ExecPlan.from_bytes(exec_plan.to_bytes())
+    assert torch.equal(restored_exec.input_ids, exec_plan.input_ids)
+    assert torch.equal(restored_exec.positions, exec_plan.positions)
+    assert torch.equal(restored_exec.out_loc, exec_plan.out_loc)
+
+
+@call_if_main()
+def test_plan_smaller_than_json():
+    plan = _make_plan(128)
+    json_size = len(
+        json.dumps(
+            {
+                "phase": plan.phase,
+                "uids": plan.uids.tolist(),
+                "table_idxs": plan.table_idxs.tolist(),
+                "cached_lens": plan.cached_lens.tolist(),
+                "device_lens": plan.device_lens.tolist(),
+            }
+        )
+    )
+    binary_size = len(plan.to_bytes())
+    logger.info(f"binary: {binary_size} bytes, json: {json_size} bytes")
+    assert binary_size < json_size
@@ -0,0 +1,195 @@
+from __future__ import annotations
+
+import torch
+from minisgl.core import Req, SamplingParams
+from minisgl.kvcache.naive_manager import NaiveCacheHandle
+from minisgl.scheduler.cache import CacheManager
+from minisgl.scheduler.decode import DecodeManager
+from minisgl.scheduler.prefill import ChunkedReq, PrefillAdder, PrefillGate, PrefillManager
+from minisgl.scheduler.table import TableManager
+from minisgl.scheduler.utils import PendingReq
+from minisgl.utils import call_if_main, init_logger
+
+logger = init_logger(__name__)
+
+
+def make_pending(uid: int, input_ids: list, max_tokens: int = 8) -> PendingReq:
+    return PendingReq(
+        uid=uid,
+        input_ids=torch.tensor(input_ids, dtype=torch.int32),
+        sampling_params=SamplingParams(max_tokens=max_tokens),
+    )
+
+
+@call_if_main()
+def test_estimate_kv_growth():
+    cache_manager = CacheManager(torch.device("cpu"), num_pages=256, type="radix")
+    # warm the cache with a known prefix
+    cached_ids = torch.tensor([1, 2, 3, 4], dtype=torch.int32)
+    cache_manager.manager.insert_prefix(cached_ids, torch.arange(4, dtype=torch.int32))
+
+    pending = [
+        # matches the 4-token cached prefix (match_req drops the last token)
+        make_pending(0, [1, 2, 3, 4, 5, 6], max_tokens=8),
+        # fully cold
+        make_pending(1, [7, 8, 9], max_tokens=2),
+    ]
+    expected = ((6 - 4) + 8) + ((3 - 0) + 2)
+    assert cache_manager.estimate_kv_growth(pending) == expected
+    # the dry run did not lock anything, nor did it count as a cache hit
+    assert cache_manager.manager.size_info.protected_size == 0
+    assert cache_manager.manager.total_cached_tokens_served() == 0
+
+
+@call_if_main()
+def test_prefill_decode_transition():
+    pending = make_pending(0, list(range(1, 11)), max_tokens=4)  # 10-token prompt
+    # chunks of 4: only the last chunk makes the request decode-ready
+    for device_len, last in [(4, False), (8, False), (10, True)]:
+        CLS = Req if last else ChunkedReq
+        req = CLS(
+            input_ids=pending.input_ids[:device_len],
+            table_idx=0,
+            cached_len=0,
+            output_len=pending.output_len,
+            uid=pending.uid,
+            sampling_params=pending.sampling_params,
+            cache_handle=NaiveCacheHandle(0),
+        )
+        assert req.is_last_prefill_chunk(pending.input_len) == last
+        assert pending.fully_prefilled(device_len) == last
+
+
+def make_prefill_manager(max_running_reqs: int = 8, deterministic: bool = False) -> PrefillManager:
+    return PrefillManager(
+        cache_manager=CacheManager(torch.device("cpu"), num_pages=256, type="radix"),
+        table_manager=TableManager(
+            max_running_reqs=max_running_reqs,
+            page_table=torch.zeros(max_running_reqs, 64, dtype=torch.int32),
+        ),
+        decode_manager=DecodeManager(),
+        deterministic=deterministic,
+    )
+
+
+@call_if_main()
+def test_schedule_all():
+    manager = make_prefill_manager()
+    for uid, input_len in enumerate([6, 10, 3]):
+        manager.pending_list.append(make_pending(uid, list(range(1, input_len + 1))))
+
+    batches = manager.schedule_all(per_batch_budget=8)
+    assert len(manager.pending_list) == 0
+    assert [[req.uid for req in batch.reqs] for batch in batches] == [[0, 1], [1], [2]]
+    # the chunked request finishes its prompt across the first two batches
+    assert isinstance(batches[0].reqs[1], ChunkedReq) and batches[0].reqs[1].device_len == 2
+    assert not isinstance(batches[1].reqs[0], ChunkedReq)
+    assert batches[1].reqs[0].device_len == 10
+
+    # admission stalling on capacity stops the drain instead of spinning
+    manager = make_prefill_manager(max_running_reqs=2)
+    for uid in range(3):
+        manager.pending_list.append(make_pending(uid, [1, 2, 3]))
+    batches = manager.schedule_all(per_batch_budget=1024)
+    assert len(batches) == 1 and len(batches[0].reqs) == 2
+    assert len(manager.pending_list) == 1
+
+
+@call_if_main()
+def test_prefill_gate():
+    # the default admits prefill on every step (current behavior)
+    gate = PrefillGate()
+    for _ in range(3):
+        assert gate.allow_prefill(decode_runnable=True)
+        gate.on_batch("prefill")
+
+    # ratio 2: each prefill must be followed by two decode steps
+    gate = PrefillGate(prefill_decode_ratio=2)
+    phases: list = []
+    pending_prefills = 3
+    for _ in range(9):
+        if pending_prefills > 0 and gate.allow_prefill(decode_runnable=True):
+            phase = "prefill"
+            pending_prefills -= 1
+        else:
+            phase = "decode"
+        gate.on_batch(phase)
+        phases.append(phase)
+    assert phases == ["prefill", "decode", "decode"] * 3
+
+    # with no decode work, gating would only idle the GPU, so prefill goes ahead
+    gate = PrefillGate(prefill_decode_ratio=2)
+    assert gate.allow_prefill(decode_runnable=False)
+
+
+@call_if_main()
+def test_max_chunk_size():
+    cache_manager = CacheManager(torch.device("cpu"), num_pages=256, type="radix")
+    table_manager = TableManager(
+        max_running_reqs=4, page_table=torch.zeros(4, 64, dtype=torch.int32)
+    )
+    adder = PrefillAdder(
+        token_budget=1024,  # large enough to fit the whole request
+        reserved_size=0,
+        cache_manager=cache_manager,
+        table_manager=table_manager,
+        max_chunk_size=4,
+    )
+    req = adder.try_add_one(make_pending(0, list(range(1, 11)), max_tokens=2))
+    assert isinstance(req, ChunkedReq)
+    assert req.device_len == 4
+    # the budget was only charged for the chunk
+    assert adder.token_budget == 1024 - 4
+
+
+@call_if_main()
+def test_chunked_reservation_counts_output_once():
+    cache_manager = CacheManager(torch.device("cpu"), num_pages=256, type="radix")
+    table_manager = TableManager(
+        max_running_reqs=4, page_table=torch.zeros(4, 64, dtype=torch.int32)
+    )
+
+    def make_adder() -> PrefillAdder:
+        # one adder per batch, as the scheduler does
+        return PrefillAdder(
+            token_budget=1024,
+            reserved_size=0,
+            cache_manager=cache_manager,
+            table_manager=table_manager,
+            max_chunk_size=3,
+        )
+
+    pending = make_pending(0, list(range(1, 10)), max_tokens=5)  # 9-token prompt
+    charges = []
+    for expected_device_len in [3, 6, 9]:
+        adder = make_adder()
+        req = adder.try_add_one(pending)
+        assert req is not None and req.device_len == expected_device_len
+        charges.append(adder.reserved_size)
+        req.cached_len = req.device_len  # the chunk's KV exists after the step
+        pending.chunked_req = req if isinstance(req, ChunkedReq) else None
+
+    # the first chunk reserves the full remainder plus the output; later
+    # chunks only charge their own extend, so output_len is counted once
+    assert charges == [9 + 5, 3, 3]
+
+
+@call_if_main()
+def test_deterministic_admission():
+    input_lens = {0: 6, 1: 10, 2: 3}
+
+    def drain(order: list) -> list:
+        manager = make_prefill_manager(deterministic=True)
+        for uid in order:
+            manager.pending_list.append(make_pending(uid, list(range(1, input_lens[uid] + 1))))
+        return [[req.uid for req in batch.reqs] for batch in manager.schedule_all(8)]
+
+    # the same pending set yields the same batches whatever the arrival order
+    assert drain([0, 1, 2]) == drain([2, 0, 1]) == drain([1, 2, 0]) == [[0, 1], [1], [2]]
+
+    # without the flag, insertion order still decides
+    manager = make_prefill_manager()
+    for uid in [2, 0, 1]:
+        manager.pending_list.append(make_pending(uid, list(range(1, input_lens[uid] + 1))))
+    batch = manager.schedule_next_batch(8)
+    assert batch is not None and [req.uid for req in batch.reqs] == [2, 0]
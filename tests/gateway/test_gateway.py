@@ -0,0 +1,853 @@
+from __future__ import annotations
+
+import httpx
+from fastapi.testclient import TestClient
+from minisgl.gateway import GatewayConfig, WorkerPool
+from minisgl.gateway.api_server import create_gateway_app
+from minisgl.utils import call_if_main, init_logger
+
+logger = init_logger(__name__)
+
+WORKER_A = "http://worker-a:1919"
+WORKER_B = "http://worker-b:1919"
+
+
+def make_client(**kwargs) -> TestClient:
+    config = GatewayConfig(workers=[WORKER_A, WORKER_B], admin_token="secret", **kwargs)
+    return TestClient(create_gateway_app(config))
+
+
+class MockWorker:
+    """Replaces the gateway's upstream client with an in-memory transport."""
+
+    def __init__(self, client: TestClient, responder=None) -> None:
+        self.requests: list[httpx.Request] = []
+        self.responder = responder or (lambda _: httpx.Response(200, json={"ok": True}))
+        client.app.state.client = httpx.AsyncClient(  # type: ignore[attr-defined]
+            transport=httpx.MockTransport(self._handle)
+        )
+
+    def _handle(self, request: httpx.Request) -> httpx.Response:
+        self.requests.append(request)
+        return self.responder(request)
+
+
+@call_if_main()
+def test_worker_pool_selection():
+    pool = WorkerPool([WORKER_A, WORKER_B])
+    assert pool.select() is not None
+    pool.workers[0].inflight = 3
+    assert pool.select().url == WORKER_B
+
+    # draining workers are skipped for new requests
+    assert pool.set_draining(WORKER_B, True)
+    assert pool.select().url == WORKER_A
+    assert pool.set_draining(WORKER_B, False)
+    assert pool.select().url == WORKER_B
+    assert not pool.set_draining("http://nope:1", True)
+
+
+@call_if_main()
+def test_admin_drain_endpoints():
+    with make_client() as client:
+        # admin routes require the token
+        assert client.get("/admin/workers").status_code == 403
+        headers = {"X-Admin-Token": "secret"}
+
+        resp = client.get("/admin/workers", headers=headers)
+        assert resp.status_code == 200
+        assert [w["draining"] for w in resp.json()["workers"]] == [False, False]
+
+        resp = client.post("/admin/workers/drain", json={"url": WORKER_A}, headers=headers)
+        assert resp.status_code == 200
+        resp = client.get("/admin/workers", headers=headers)
+        assert [w["draining"] for w in resp.json()["workers"]] == [True, False]
+
+        # unknown workers are rejected
+        resp = client.post("/admin/workers/drain", json={"url": "http://nope:1"}, headers=headers)
+        assert resp.status_code == 400
+
+        resp = client.post("/admin/workers/enable", json={"url": WORKER_A}, headers=headers)
+        assert resp.status_code == 200
+        resp = client.get("/admin/workers", headers=headers)
+        assert [w["draining"] for w in resp.json()["workers"]] == [False, False]
+
+
+@call_if_main()
+def test_upstream_prefix_rewrite():
+    with make_client(upstream_prefix="/openai") as client:
+        worker = MockWorker(client)
+        resp = client.post(
+            "/v1/chat/completions",
+            json={"model": "m", "messages": [{"role": "user", "content": "hi"}]},
+        )
+        assert resp.status_code == 200
+        assert len(worker.requests) == 1
+        assert worker.requests[0].url.path == "/openai/v1/chat/completions"
+
+
+@call_if_main()
+def test_body_validation():
+    with make_client() as client:
+        worker = MockWorker(client)
+        bad_bodies = [
+            {"model": "m"},  # neither messages nor prompt
+            {"model": "m", "messages": []},  # empty messages
+            {"model": "m", "messages": [{"role": "user"}]},  # missing content
+            {"model": "m", "prompt": 42},  # prompt of the wrong type
+        ]
+        for body in bad_bodies:
+            resp = client.post("/v1/chat/completions", json=body)
+            assert resp.status_code == 400, body
+            assert resp.json()["error"]["type"] == "invalid_request_error"
+        assert len(worker.requests) == 0  # nothing was proxied
+
+        # a well-formed request (with extra fields) passes through
+        resp = client.post(
+            "/v1/chat/completions",
+            json={
+                "model": "m",
+                "messages": [{"role": "user", "content": "hi"}],
+                "some_extra_field": {"a": 1},
+            },
+        )
+        assert resp.status_code == 200
+        assert len(worker.requests) == 1
+
+
+@call_if_main()
+def test_error_envelope_normalization():
+    body = {"model": "m", "messages": [{"role": "user", "content": "hi"}]}
+
+    # a bare non-OpenAI error body gets wrapped into the canonical envelope
+    with make_client() as client:
+        MockWorker(client, responder=lambda _: httpx.Response(400, json={"detail": "bad"}))
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 400
+        error = resp.json()["error"]
+        assert error["message"] == "bad"
+        assert error["type"] == "upstream_error" and error["code"] == 400
+
+    # a well-formed OpenAI error passes through unchanged
+    openai_error = {"error": {"message": "boom", "type": "server_error", "code": 500}}
+    with make_client() as client:
+        MockWorker(client, responder=lambda _: httpx.Response(500, json=openai_error))
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 500
+        assert resp.json() == openai_error
+
+    # even a non-JSON error body yields a normalized envelope
+    with make_client() as client:
+        MockWorker(client, responder=lambda _: httpx.Response(503, text="overloaded"))
+        resp = client.post("/v1/chat/completions", json=body)
+        error = resp.json()["error"]
+        assert error["message"] == "overloaded" and error["code"] == 503
+
+
+@call_if_main()
+def test_response_compression():
+    body = {"model": "m", "messages": [{"role": "user", "content": "hi"}]}
+    large_payload = {"ok": True, "text": "x" * 4096}
+    with make_client(compression=True) as client:
+        MockWorker(client, responder=lambda _: httpx.Response(200, json=large_payload))
+        resp = client.post("/v1/chat/completions", json=body, headers={"Accept-Encoding": "gzip"})
+        assert resp.status_code == 200
+        assert resp.headers.get("Content-Encoding") == "gzip"
+        assert resp.json() == large_payload  # the client transparently inflates
+
+        # clients that do not accept gzip get the identity body
+        resp = client.post(
+            "/v1/chat/completions", json=body, headers={"Accept-Encoding": "identity"}
+        )
+        assert "Content-Encoding" not in resp.headers
+
+    # small bodies are not worth compressing
+    with make_client(compression=True) as client:
+        MockWorker(client)
+        resp = client.post("/v1/chat/completions", json=body, headers={"Accept-Encoding": "gzip"})
+        assert "Content-Encoding" not in resp.headers
+
+    # disabled by default
+    with make_client() as client:
+        MockWorker(client, responder=lambda _: httpx.Response(200, json=large_payload))
+        resp = client.post("/v1/chat/completions", json=body, headers={"Accept-Encoding": "gzip"})
+        assert "Content-Encoding" not in resp.headers
+
+
+@call_if_main()
+def test_tokenize_stream():
+    import json
+
+    import torch
+    from minisgl.tokenizer.tokenize import TokenizeManager
+
+    class CharTokenizer:
+        """Maps each character to its codepoint, so outputs are predictable."""
+
+        def encode(self, prompt: str, return_tensors: str | None = None) -> torch.Tensor:
+            return torch.tensor([[ord(c) for c in prompt]], dtype=torch.int64)
+
+    with make_client() as client:
+        # without a configured tokenizer the routes answer 503
+        assert client.post("/tokenize", json={"text": "hi"}).status_code == 503
+
+        client.app.state.tokenize_manager = TokenizeManager(CharTokenizer())  # type: ignore[attr-defined, arg-type]
+        full = client.post("/tokenize", json={"text": "hello world"}).json()
+        assert full["count"] == len("hello world")
+
+        resp = client.post("/tokenize/stream", json={"text": "hello world", "batch_size": 4})
+        assert resp.status_code == 200
+        streamed: list[int] = []
+        chunks = 0
+        for line in resp.iter_lines():
+            if not line.startswith("data:"):
+                continue
+            payload = line.removeprefix("data:").strip()
+            if payload == "[DONE]":
+                break
+            streamed.extend(json.loads(payload)["token_ids"])
+            chunks += 1
+        # the stream is batched, and concatenates to the non-streamed result
+        assert chunks == 3  # ceil(11 / 4)
+        assert streamed == full["token_ids"]
+
+        assert client.post("/tokenize/stream", json={"text": 1}).status_code == 400
+        assert (
+            client.post("/tokenize/stream", json={"text": "x", "batch_size": 0}).status_code
+            == 400
+        )
+
+
+@call_if_main()
+def test_tokenize_count():
+    import torch
+    from minisgl.tokenizer.tokenize import TokenizeManager
+
+    class CharTokenizer:
+        def encode(self, prompt: str, return_tensors: str | None = None) -> torch.Tensor:
+            return torch.tensor([[ord(c) for c in prompt]], dtype=torch.int64)
+
+    with make_client() as client:
+        assert client.post("/tokenize/count", json={"prompts": ["hi"]}).status_code == 503
+
+        client.app.state.tokenize_manager = TokenizeManager(CharTokenizer())  # type: ignore[attr-defined, arg-type]
+        prompts = ["hello", "", "hello world"]
+        resp = client.post("/tokenize/count", json={"prompts": prompts})
+        assert resp.status_code == 200
+        assert resp.json()["counts"] == [len(p) for p in prompts]
+
+        assert client.post("/tokenize/count", json={"prompts": "hello"}).status_code == 400
+        assert client.post("/tokenize/count", json={"prompts": [1, 2]}).status_code == 400
+
+
+@call_if_main()
+def test_debug_render():
+    import torch
+    from minisgl.tokenizer.tokenize import TokenizeManager
+
+    class TemplateTokenizer:
+        def encode(self, prompt: str, return_tensors: str | None = None) -> torch.Tensor:
+            return torch.tensor([[ord(c) for c in prompt]], dtype=torch.int64)
+
+        def apply_chat_template(
+            self, messages, tokenize: bool, add_generation_prompt: bool
+        ) -> str:
+            rendered = "".join(f"<{m['role']}>{m['content']}" for m in messages)
+            return rendered + ("<assistant>" if add_generation_prompt else "")
+
+    with make_client() as client:
+        assert client.post("/debug/render", json={"messages": [{}]}).status_code == 503
+
+        client.app.state.tokenize_manager = TokenizeManager(TemplateTokenizer())  # type: ignore[attr-defined, arg-type]
+        messages = [
+            {"role": "system", "content": "be brief"},
+            {"role": "user", "content": "hello there"},
+        ]
+        resp = client.post("/debug/render", json={"messages": messages})
+        assert resp.status_code == 200
+        data = resp.json()
+        assert "be brief" in data["rendered"] and "hello there" in data["rendered"]
+        assert data["rendered"].endswith("<assistant>")
+        assert data["count"] == len(data["rendered"])
+
+        # templating options are honored
+        resp = client.post(
+            "/debug/render", json={"messages": messages, "add_generation_prompt": False}
+        )
+        assert not resp.json()["rendered"].endswith("<assistant>")
+
+        assert client.post("/debug/render", json={"messages": []}).status_code == 400
+
+
+@call_if_main()
+def test_model_allowlist():
+    with make_client(allowed_models=["served-model"]) as client:
+        worker = MockWorker(client)
+        messages = [{"role": "user", "content": "hi"}]
+
+        resp = client.post(
+            "/v1/chat/completions", json={"model": "other-model", "messages": messages}
+        )
+        assert resp.status_code == 404
+        assert resp.json()["error"]["type"] == "model_not_found"
+        assert len(worker.requests) == 0  # rejected before reaching a worker
+
+        resp = client.post(
+            "/v1/chat/completions", json={"model": "served-model", "messages": messages}
+        )
+        assert resp.status_code == 200
+        assert len(worker.requests) == 1
+
+    # an empty allowlist keeps the current allow-everything behavior
+    with make_client() as client:
+        MockWorker(client)
+        resp = client.post(
+            "/v1/chat/completions", json={"model": "anything", "messages": messages}
+        )
+        assert resp.status_code == 200
+
+
+@call_if_main()
+def test_inflight_cap_429():
+    body = {"model": "m", "messages": [{"role": "user", "content": "hi"}]}
+    with make_client(max_inflight=2) as client:
+        worker = MockWorker(client)
+        pool: WorkerPool = client.app.state.pool  # type: ignore[attr-defined]
+
+        # saturate the cap: the request is rejected with a back-off hint
+        pool.workers[0].inflight = 2
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 429
+        assert resp.json()["error"]["type"] == "rate_limit_error"
+        assert resp.json()["estimated_wait_ms"] > 0
+        assert len(worker.requests) == 0
+
+        # below the cap, traffic flows again
+        pool.workers[0].inflight = 0
+        assert client.post("/v1/chat/completions", json=body).status_code == 200
+
+
+@call_if_main()
+def test_failover_on_5xx():
+    body = {"model": "m", "messages": [{"role": "user", "content": "hi"}]}
+
+    def responder(request: httpx.Request) -> httpx.Response:
+        if request.url.host == "worker-a":
+            return httpx.Response(500, json={"error": "boom"})
+        return httpx.Response(200, json={"ok": True})
+
+    with make_client(failover_on_5xx=True) as client:
+        worker = MockWorker(client, responder=responder)
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 200
+        assert [r.url.host for r in worker.requests] == ["worker-a", "worker-b"]
+
+    # disabled by default: the 500 is surfaced as-is
+    with make_client() as client:
+        worker = MockWorker(client, responder=responder)
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 500
+        assert len(worker.requests) == 1
+
+
+@call_if_main()
+def test_target_worker_pinning():
+    body = {"model": "m", "messages": [{"role": "user", "content": "hi"}]}
+    with make_client() as client:
+        worker = MockWorker(client)
+        headers = {"X-Admin-Token": "secret", "X-Target-Worker": WORKER_B}
+
+        # make A strictly preferable so only the pin can route to B
+        pool: WorkerPool = client.app.state.pool  # type: ignore[attr-defined]
+        pool.workers[1].inflight = 10
+        for _ in range(3):
+            resp = client.post("/v1/chat/completions", json=body, headers=headers)
+            assert resp.status_code == 200
+            assert resp.headers["X-Served-By-Pool"] == "pinned"
+        assert all(r.url.host == "worker-b" for r in worker.requests)
+
+        # pinning requires admin auth and a known worker
+        resp = client.post(
+            "/v1/chat/completions", json=body, headers={"X-Target-Worker": WORKER_B}
+        )
+        assert resp.status_code == 403
+        resp = client.post(
+            "/v1/chat/completions",
+            json=body,
+            headers={"X-Admin-Token": "secret", "X-Target-Worker": "http://nope:1"},
+        )
+        assert resp.status_code == 400
+
+
+@call_if_main()
+def test_canary_split():
+    body = {"model": "m", "messages": [{"role": "user", "content": "hi"}]}
+    canary_url = "http://canary:1919"
+
+    with make_client(canary_workers=[canary_url], canary_percent=100) as client:
+        worker = MockWorker(client)
+        for _ in range(3):
+            resp = client.post("/v1/chat/completions", json=body, headers={"X-User-Id": "u1"})
+            assert resp.status_code == 200
+            assert resp.headers["X-Served-By-Pool"] == "canary"
+        assert all(r.url.host == "canary" for r in worker.requests)
+
+    # 0% keeps everything on the primary pool
+    with make_client(canary_workers=[canary_url], canary_percent=0) as client:
+        worker = MockWorker(client)
+        resp = client.post("/v1/chat/completions", json=body, headers={"X-User-Id": "u1"})
+        assert resp.headers["X-Served-By-Pool"] == "primary"
+        assert worker.requests[0].url.host != "canary"
+
+
+@call_if_main()
+def test_upstream_error_status_mapping():
+    body = {"model": "m", "messages": [{"role": "user", "content": "hi"}]}
+
+    def timeout_responder(request: httpx.Request) -> httpx.Response:
+        raise httpx.ReadTimeout("worker too slow", request=request)
+
+    with make_client() as client:
+        MockWorker(client, responder=timeout_responder)
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 504
+        assert resp.json()["error"]["type"] == "upstream_timeout"
+
+    def connect_responder(request: httpx.Request) -> httpx.Response:
+        raise httpx.ConnectError("connection refused", request=request)
+
+    with make_client() as client:
+        MockWorker(client, responder=connect_responder)
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 502
+        assert resp.json()["error"]["type"] == "upstream_error"
+        # the catch-all proxy maps errors the same way
+        assert client.post("/v1/rerank", json={}).status_code == 502
+
+
+@call_if_main()
+def test_catch_all_proxy():
+    with make_client() as client:
+        worker = MockWorker(client)
+        resp = client.post("/v1/rerank", json={"query": "q", "documents": ["a", "b"]})
+        assert resp.status_code == 200
+        assert worker.requests[0].method == "POST"
+        assert worker.requests[0].url.path == "/v1/rerank"
+
+        resp = client.get("/v1/models?verbose=1", headers={"X-Custom": "yes"})
+        assert resp.status_code == 200
+        assert worker.requests[1].method == "GET"
+        assert worker.requests[1].url.path == "/v1/models"
+        assert worker.requests[1].url.query == b"verbose=1"
+        assert worker.requests[1].headers["X-Custom"] == "yes"
+
+        # the specialized chat handler still validates bodies first
+        resp = client.post("/v1/chat/completions", json={"model": "m"})
+        assert resp.status_code == 400
+
+
+@call_if_main()
+def test_n_fan_out():
+    import json
+
+    counter = {"calls": 0}
+
+    def responder(_: httpx.Request) -> httpx.Response:
+        counter["calls"] += 1
+        return httpx.Response(
+            200,
+            json={
+                "object": "chat.completion",
+                "choices": [{"index": 0, "message": {"content": f"c{counter['calls']}"}}],
+                "usage": {
+                    "prompt_tokens": 3,
+                    "completion_tokens": 1,
+                    "total_tokens": 4,
+                    # nested usage blocks must merge, not TypeError
+                    "prompt_tokens_details": {"cached_tokens": 2},
+                },
+            },
+        )
+
+    with make_client(workers_support_n=False) as client:
+        worker = MockWorker(client, responder=responder)
+        resp = client.post(
+            "/v1/chat/completions",
+            json={"model": "m", "messages": [{"role": "user", "content": "hi"}], "n": 3},
+        )
+        assert resp.status_code == 200
+        choices = resp.json()["choices"]
+        assert [c["index"] for c in choices] == [0, 1, 2]
+        assert [c["message"]["content"] for c in choices] == ["c1", "c2", "c3"]
+        assert resp.json()["usage"]["total_tokens"] == 12
+        assert resp.json()["usage"]["prompt_tokens_details"] == {"cached_tokens": 6}
+
+        # every upstream call was a single-completion request to one worker
+        assert len(worker.requests) == 3
+        assert len({r.url.host for r in worker.requests}) == 1
+        assert all(json.loads(r.content)["n"] == 1 for r in worker.requests)
+
+        # a timeout override applies to every fanned-out call
+        proxied = len(worker.requests)
+        resp = client.post(
+            "/v1/chat/completions",
+            json={"model": "m", "messages": [{"role": "user", "content": "hi"}], "n": 2},
+            headers={"X-Request-Timeout-Ms": "120000"},
+        )
+        assert resp.status_code == 200
+        fanned = worker.requests[proxied:]
+        assert len(fanned) == 2
+        assert all(r.extensions["timeout"]["read"] == 120.0 for r in fanned)
+
+
+@call_if_main()
+def test_queue_metrics():
+    with make_client() as client:
+        seen_inflight: list[int] = []
+
+        def responder(_: httpx.Request) -> httpx.Response:
+            # snapshot the queue depth while the proxied request is in flight
+            pool: WorkerPool = client.app.state.pool  # type: ignore[attr-defined]
+            seen_inflight.append(pool.total_inflight)
+            return httpx.Response(200, json={"ok": True})
+
+        MockWorker(client, responder=responder)
+        resp = client.get("/metrics/queue")
+        assert resp.status_code == 200
+        assert resp.json()["total_inflight"] == 0
+
+        client.post(
+            "/v1/chat/completions",
+            json={"model": "m", "messages": [{"role": "user", "content": "hi"}]},
+        )
+        assert seen_inflight == [1]
+
+        # back to idle once the request completes
+        resp = client.get("/metrics/queue")
+        assert resp.json()["total_inflight"] == 0
+        assert [w["inflight"] for w in resp.json()["workers"]] == [0, 0]
+
+
+@call_if_main()
+def test_stream_usage_synthesis():
+    import json
+
+    chunks = [
+        {"object": "chat.completion.chunk", "choices": [{"delta": {"content": "he"}}]},
+        {"object": "chat.completion.chunk", "choices": [{"delta": {"content": "llo"}}]},
+    ]
+    sse = b"".join(f"data: {json.dumps(c)}\n\n".encode() for c in chunks) + b"data: [DONE]\n\n"
+    with make_client() as client:
+        worker = MockWorker(
+            client,
+            responder=lambda _: httpx.Response(
+                200, content=sse, headers={"content-type": "text/event-stream"}
+            ),
+        )
+        resp = client.post(
+            "/v1/chat/completions",
+            json={
+                "model": "m",
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": True,
+                "stream_options": {"include_usage": True},
+            },
+        )
+        assert resp.status_code == 200
+        # the flag is forwarded to the worker unchanged
+        forwarded = json.loads(worker.requests[0].content)
+        assert forwarded["stream_options"] == {"include_usage": True}
+
+        # the worker sent no usage chunk, so the gateway appended one
+        lines = [line for line in resp.text.split("\n\n") if line]
+        assert lines[-1] == "data: [DONE]"
+        usage = json.loads(lines[-2].removeprefix("data: "))["usage"]
+        assert usage["completion_tokens"] == 2
+        assert usage["total_tokens"] == 2
+
+
+@call_if_main()
+def test_sampling_normalization():
+    import json
+
+    with make_client() as client:
+        worker = MockWorker(client)
+        resp = client.post(
+            "/v1/chat/completions",
+            json={
+                "model": "m",
+                "messages": [{"role": "user", "content": "hi"}],
+                "temperature": -0.5,
+                "top_p": 1.7,
+            },
+        )
+        assert resp.status_code == 200
+        forwarded = json.loads(worker.requests[0].content)
+        # negative temperature is clamped to greedy, which makes top_p a no-op
+        assert forwarded["temperature"] == 0.0
+        assert forwarded["top_p"] == 1.0
+
+        # a wrongly typed field cannot be clamped and fails fast with a 400
+        for field, value in (("temperature", "hot"), ("top_k", 1.5), ("top_p", [])):
+            resp = client.post(
+                "/v1/chat/completions",
+                json={
+                    "model": "m",
+                    "messages": [{"role": "user", "content": "hi"}],
+                    field: value,
+                },
+            )
+            assert resp.status_code == 400
+            assert resp.json()["error"]["type"] == "invalid_request_error"
+            assert field in resp.json()["error"]["message"]
+        assert len(worker.requests) == 1  # none of the rejects reached a worker
+
+
+@call_if_main()
+def test_shadow_mirroring():
+    body = {"model": "m", "messages": [{"role": "user", "content": "hi"}]}
+    shadow_url = "http://shadow:1919"
+
+    with make_client(shadow_workers=[shadow_url], shadow_percent=100) as client:
+        worker = MockWorker(client)
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 200
+        hosts = [req.url.host for req in worker.requests]
+        assert hosts.count("shadow") == 1
+        assert len(hosts) == 2  # one primary call plus the mirror
+
+    # a failing shadow worker never surfaces to the client
+    def responder(request: httpx.Request) -> httpx.Response:
+        if request.url.host == "shadow":
+            raise httpx.ConnectError("shadow down")
+        return httpx.Response(200, json={"ok": True})
+
+    with make_client(shadow_workers=[shadow_url], shadow_percent=100) as client:
+        MockWorker(client, responder)
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 200
+
+    # compression replaces the outgoing Response; the mirror task rides along
+    large_payload = {"ok": True, "text": "x" * 4096}
+    with make_client(
+        shadow_workers=[shadow_url], shadow_percent=100, compression=True
+    ) as client:
+        worker = MockWorker(client, responder=lambda _: httpx.Response(200, json=large_payload))
+        resp = client.post("/v1/chat/completions", json=body, headers={"Accept-Encoding": "gzip"})
+        assert resp.status_code == 200
+        assert resp.headers.get("Content-Encoding") == "gzip"
+        assert [req.url.host for req in worker.requests].count("shadow") == 1
+
+    # percent 0 configures the pool but mirrors nothing
+    with make_client(shadow_workers=[shadow_url], shadow_percent=0) as client:
+        worker = MockWorker(client)
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 200
+        assert [req.url.host for req in worker.requests] == ["worker-a"]
+
+
+@call_if_main()
+def test_deep_health_check():
+    def responder(request: httpx.Request) -> httpx.Response:
+        if request.url.path.endswith("/healthz"):
+            return httpx.Response(200, json={"status": "ok"})
+        # generation is wedged even though the liveness probe passes
+        return httpx.Response(500, json={"detail": "cuda error"})
+
+    # the shallow probe is fooled by the passing /healthz
+    with make_client() as client:
+        MockWorker(client, responder)
+        resp = client.get("/readyz")
+        assert resp.status_code == 200
+        assert all(w["healthy"] for w in resp.json()["workers"])
+
+    # the deep probe demands an actual completion and marks the pool unready
+    with make_client(deep_health=True) as client:
+        MockWorker(client, responder)
+        resp = client.get("/readyz")
+        assert resp.status_code == 503
+        assert resp.json()["status"] == "unready"
+        assert not any(w["healthy"] for w in resp.json()["workers"])
+
+    # a worker that generates a well-formed completion is deep-ready
+    def healthy_responder(request: httpx.Request) -> httpx.Response:
+        if request.url.path.endswith("/healthz"):
+            return httpx.Response(200, json={"status": "ok"})
+        return httpx.Response(200, json={"choices": [{"message": {"content": "!"}}]})
+
+    with make_client(deep_health=True) as client:
+        MockWorker(client, healthy_responder)
+        assert client.get("/readyz").status_code == 200
+
+
+@call_if_main()
+def test_empty_upstream_body():
+    body = {"model": "m", "messages": [{"role": "user", "content": "hi"}]}
+
+    # a 200 with no body is a worker bug, surfaced as 502 instead of an
+    # empty success
+    with make_client() as client:
+        MockWorker(client, lambda _: httpx.Response(200, content=b""))
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 502
+        assert resp.json()["error"]["type"] == "upstream_error"
+
+    # with failover enabled, a healthy worker rescues the request
+    def responder(request: httpx.Request) -> httpx.Response:
+        if request.url.host == "worker-a":
+            return httpx.Response(200, content=b"")
+        return httpx.Response(200, json={"ok": True})
+
+    with make_client(failover_on_5xx=True) as client:
+        worker = MockWorker(client, responder)
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 200
+        assert {req.url.host for req in worker.requests} == {"worker-a", "worker-b"}
+
+
+@call_if_main()
+def test_request_timeout_override():
+    body = {"model": "m", "messages": [{"role": "user", "content": "hi"}]}
+
+    with make_client() as client:
+        worker = MockWorker(client)
+        # a valid override becomes the per-request read timeout upstream
+        resp = client.post(
+            "/v1/chat/completions", json=body, headers={"X-Request-Timeout-Ms": "120000"}
+        )
+        assert resp.status_code == 200
+        assert worker.requests[0].extensions["timeout"]["read"] == 120.0
+
+        # without the header the client default applies (no per-request value)
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 200
+        assert worker.requests[1].extensions["timeout"]["read"] != 120.0
+
+        # over the cap and malformed values are rejected before proxying
+        proxied = len(worker.requests)
+        for value in ["600001", "0", "-5", "soon"]:
+            resp = client.post(
+                "/v1/chat/completions", json=body, headers={"X-Request-Timeout-Ms": value}
+            )
+            assert resp.status_code == 400, value
+            assert resp.json()["error"]["type"] == "invalid_request_error"
+        assert len(worker.requests) == proxied
+
+
+@call_if_main()
+def test_cached_tokens_header():
+    body = {"model": "m", "messages": [{"role": "user", "content": "hi"}]}
+    payload = {
+        "choices": [{"message": {"content": "hey"}}],
+        "usage": {"prompt_tokens": 50, "prompt_tokens_details": {"cached_tokens": 37}},
+    }
+
+    with make_client() as client:
+        MockWorker(client, lambda _: httpx.Response(200, json=payload))
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 200
+        assert resp.headers["X-Cache-Cached-Tokens"] == "37"
+
+    # a flat cached_tokens field is also understood
+    flat = {"choices": [{}], "usage": {"cached_tokens": 4}}
+    with make_client() as client:
+        MockWorker(client, lambda _: httpx.Response(200, json=flat))
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.headers["X-Cache-Cached-Tokens"] == "4"
+
+    # without cache metadata the header is simply absent
+    with make_client() as client:
+        MockWorker(client)
+        resp = client.post("/v1/chat/completions", json=body)
+        assert "X-Cache-Cached-Tokens" not in resp.headers
+
+
+@call_if_main()
+def test_model_worker_pools():
+    model_workers = {
+        "llama": ["http://llama-worker:1919"],
+        "qwen": ["http://qwen-worker:1919"],
+    }
+
+    def ask(client: TestClient, model: str):
+        return client.post(
+            "/v1/chat/completions",
+            json={"model": model, "messages": [{"role": "user", "content": "hi"}]},
+        )
+
+    with make_client(model_workers=model_workers) as client:
+        worker = MockWorker(client)
+        # each model lands on its dedicated pool
+        assert ask(client, "llama").headers["X-Served-By-Pool"] == "model"
+        assert ask(client, "qwen").status_code == 200
+        assert [req.url.host for req in worker.requests] == ["llama-worker", "qwen-worker"]
+
+        # unmapped models fall back to the flat default pool
+        resp = ask(client, "mystery")
+        assert resp.status_code == 200
+        assert resp.headers["X-Served-By-Pool"] == "primary"
+        assert worker.requests[-1].url.host == "worker-a"
+
+    # without a default pool, unmapped models are a hard 404
+    config = GatewayConfig(workers=[], model_workers=model_workers)
+    with TestClient(create_gateway_app(config)) as client:
+        worker = MockWorker(client)
+        assert ask(client, "llama").status_code == 200
+        resp = ask(client, "mystery")
+        assert resp.status_code == 404
+        assert resp.json()["error"]["type"] == "model_not_found"
+
+
+@call_if_main()
+def test_static_fallback_completion():
+    body = {"model": "m", "messages": [{"role": "user", "content": "hi"}]}
+
+    def down_responder(request: httpx.Request) -> httpx.Response:
+        raise httpx.ConnectError("connection refused", request=request)
+
+    fallback = "Service temporarily unavailable, please retry"
+    with make_client(fallback_message=fallback) as client:
+        MockWorker(client, responder=down_responder)
+        resp = client.post("/v1/chat/completions", json=body)
+        # total failure degrades to a valid completion, not a 502
+        assert resp.status_code == 200
+        assert resp.headers["X-Served-By-Pool"] == "fallback"
+        choice = resp.json()["choices"][0]
+        assert choice["message"] == {"role": "assistant", "content": fallback}
+        assert choice["finish_reason"] == "stop"
+
+        # a healthy worker is still preferred over the fallback
+        MockWorker(client)
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 200
+        assert resp.headers["X-Served-By-Pool"] == "primary"
+
+    # without the option the 502 behavior holds
+    with make_client() as client:
+        MockWorker(client, responder=down_responder)
+        resp = client.post("/v1/chat/completions", json=body)
+        assert resp.status_code == 502
+
+
+@call_if_main()
+def test_startup_warmup():
+    import json
+    import time
+
+    client = make_client(warmup=True)
+    # installing the mock before startup guarantees warmup hits it, not the net
+    worker = MockWorker(client)
+    with client:
+        # the warmup task runs on the app's event loop shortly after startup
+        deadline = time.monotonic() + 2.0
+        while time.monotonic() < deadline and len(worker.requests) < 2:
+            time.sleep(0.02)
+        assert sorted(req.url.host for req in worker.requests) == ["worker-a", "worker-b"]
+        assert json.loads(worker.requests[0].content)["model"] == "warmup"
+
+    # without the flag nothing is primed
+    client = make_client()
+    worker = MockWorker(client)
+    with client:
+        time.sleep(0.2)
+        assert worker.requests == []
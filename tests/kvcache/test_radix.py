@@ -0,0 +1,555 @@
+from __future__ import annotations
+
+import torch
+from minisgl.kvcache.radix_manager import RadixCacheManager
+from minisgl.utils import call_if_main, init_logger
+
+logger = init_logger(__name__)
+
+
+def _ids(*values: int) -> torch.Tensor:
+    return torch.tensor(values, dtype=torch.int32)
+
+
+@call_if_main()
+def test_node_metadata():
+    manager = RadixCacheManager(torch.device("cpu"))
+    input_ids = _ids(1, 2, 3, 4)
+    manager.insert_prefix(input_ids, _ids(10, 11, 12, 13), metadata="image-hash-0")
+
+    handle, indices = manager.match_prefix(input_ids)
+    assert handle.cached_len == 4
+    assert indices.tolist() == [10, 11, 12, 13]
+    assert handle.metadata == "image-hash-0"
+
+    # metadata survives a node split
+    handle, _ = manager.match_prefix(_ids(1, 2))
+    assert handle.cached_len == 2
+    assert handle.metadata == "image-hash-0"
+
+
+@call_if_main()
+def test_handle_identity():
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+
+    first, _ = manager.match_prefix(_ids(1, 2, 3, 4))
+    second, _ = manager.match_prefix(_ids(1, 2, 3, 4))
+    assert first == second
+    assert len({first, second}) == 1
+
+    # a match landing on a different node is not equal
+    other, _ = manager.match_prefix(_ids(1, 2))
+    assert first != other
+
+
+@call_if_main()
+def test_split_alignment():
+    manager = RadixCacheManager(torch.device("cpu"), min_split_alignment=4)
+    manager.insert_prefix(_ids(1, 2, 3, 4, 5, 6, 7, 8), _ids(10, 11, 12, 13, 14, 15, 16, 17))
+
+    # a match of length 6 is rounded down to the 4-aligned boundary
+    handle, indices = manager.match_prefix(_ids(1, 2, 3, 4, 5, 6))
+    assert handle.cached_len == 4
+    assert indices.tolist() == [10, 11, 12, 13]
+
+    # a match below one block yields no cached prefix (and no mid-block split)
+    handle, indices = manager.match_prefix(_ids(1, 2, 3))
+    assert handle.cached_len == 0 and len(indices) == 0
+
+    # inserts never split mid-block either: a suffix diverging mid-block
+    # inside an existing node only splits at the aligned boundary, and the
+    # divergent part is not cached
+    manager = RadixCacheManager(torch.device("cpu"), min_split_alignment=4)
+    manager.insert_prefix(_ids(1, 2, 3, 4, 5, 6, 7, 8), _ids(10, 11, 12, 13, 14, 15, 16, 17))
+    in_cache = manager.insert_prefix(
+        _ids(1, 2, 3, 4, 5, 6, 99), _ids(10, 11, 12, 13, 14, 15, 30)
+    )
+    assert in_cache == 4  # the walk stops at the aligned boundary
+    handle, indices = manager.match_prefix(_ids(1, 2, 3, 4, 5, 6, 7, 8))
+    assert handle.cached_len == 8  # the original blocks are intact
+    assert indices.tolist() == [10, 11, 12, 13, 14, 15, 16, 17]
+    handle, _ = manager.match_prefix(_ids(1, 2, 3, 4, 5, 6, 99))
+    assert handle.cached_len == 4  # the divergent suffix was not attached
+    manager.check_integrity()
+
+    # the default alignment of 1 keeps exact matching
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2, 3, 4, 5, 6, 7, 8), _ids(10, 11, 12, 13, 14, 15, 16, 17))
+    handle, indices = manager.match_prefix(_ids(1, 2, 3, 4, 5, 6))
+    assert handle.cached_len == 6 and len(indices) == 6
+
+
+@call_if_main()
+def test_match_prefix_except_last():
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+
+    for query in [_ids(1, 2, 3, 4), _ids(1, 2, 3, 4, 5), _ids(7)]:
+        handle, indices = manager.match_prefix_except_last(query)
+        expected_handle, expected_indices = manager.match_prefix(query[:-1])
+        assert handle.cached_len == expected_handle.cached_len
+        assert indices.tolist() == expected_indices.tolist()
+
+
+@call_if_main()
+def test_preload():
+    manager = RadixCacheManager(torch.device("cpu"))
+    pinned = manager.preload(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+    # the preloaded prefix is protected, so nothing can evict it
+    assert manager.size_info.protected_size == 4
+    assert manager.size_info.evictable_size == 0
+    # preloading is not a cache hit: no request was served from it yet
+    assert manager.total_cached_tokens_served() == 0
+
+    handle, indices = manager.match_prefix(_ids(1, 2, 3, 4, 5))
+    assert handle.cached_len == 4
+    assert indices.tolist() == [10, 11, 12, 13]
+
+    manager.lock_handle(pinned, unlock=True)
+    assert manager.size_info.evictable_size == 4
+
+
+@call_if_main()
+def test_handle_liveness():
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+    handle, _ = manager.match_prefix(_ids(1, 2, 3, 4))
+    assert manager.is_handle_live(handle)
+
+    # evicting the matched leaf detaches it
+    manager.evict(4)
+    assert not manager.is_handle_live(handle)
+
+    # a fresh root handle is always live
+    cold, _ = manager.match_prefix(_ids(9, 9))
+    assert manager.is_handle_live(cold)
+
+
+@call_if_main()
+def test_event_log():
+    manager = RadixCacheManager(torch.device("cpu"), event_log_size=16)
+    manager.insert_prefix(_ids(1, 2, 3), _ids(10, 11, 12))
+    handle, _ = manager.match_prefix(_ids(1, 2, 3))
+    manager.lock_handle(handle)
+    manager.lock_handle(handle, unlock=True)
+    manager.evict(3)
+
+    ops = [(e.op, e.length) for e in manager.recent_events(10)]
+    assert ops == [("insert", 3), ("match", 3), ("lock", 3), ("unlock", 3), ("evict", 3)]
+    # events carry the node id for correlation
+    assert len({e.node_id for e in manager.recent_events(10)}) == 1
+    assert manager.recent_events(2) == manager.recent_events(10)[-2:]
+
+    # disabled by default: nothing is recorded
+    quiet = RadixCacheManager(torch.device("cpu"))
+    quiet.insert_prefix(_ids(1,), _ids(10,))
+    assert quiet.recent_events(10) == []
+
+
+@call_if_main()
+def test_cached_tokens_served():
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+    assert manager.total_cached_tokens_served() == 0
+
+    expected = 0
+    for query in [_ids(1, 2, 3, 4), _ids(1, 2), _ids(1, 2, 3, 4, 5), _ids(9)]:
+        handle, _ = manager.match_prefix(query)
+        expected += handle.cached_len
+    # the cumulative counter is exactly the sum of the per-call cached_lens
+    assert expected == 4 + 2 + 4 + 0
+    assert manager.total_cached_tokens_served() == expected
+
+
+@call_if_main()
+def test_top_prefixes():
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+    manager.insert_prefix(_ids(1, 2, 7), _ids(10, 11, 22))
+    manager.insert_prefix(_ids(9,), _ids(30,))
+
+    assert manager.top_prefixes(2) == [([1, 2, 3, 4], 4), ([1, 2, 7], 3)]
+    # asking for more than there are leaves returns everything
+    assert len(manager.top_prefixes(10)) == 3
+
+
+@call_if_main()
+def test_evict_until_free():
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2), _ids(10, 11))
+    manager.insert_prefix(_ids(3, 4, 5), _ids(20, 21, 22))
+
+    # already satisfied: nothing is evicted
+    assert len(manager.evict_until_free(target_free=4, current_free=4)) == 0
+    assert manager.size_info.evictable_size == 5
+
+    # partial: only the shortfall is evicted (up to a node boundary)
+    evicted = manager.evict_until_free(target_free=6, current_free=4)
+    assert len(evicted) >= 2
+    assert manager.size_info.evictable_size == 5 - len(evicted)
+
+    # impossible: even full eviction cannot reach the target
+    try:
+        manager.evict_until_free(target_free=100, current_free=0)
+        raise AssertionError("expected RuntimeError")
+    except RuntimeError:
+        pass
+
+
+@call_if_main()
+def test_clear_evictable():
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2, 3), _ids(10, 11, 12))
+    manager.insert_prefix(_ids(1, 2, 3, 4, 5), _ids(10, 11, 12, 13, 14))
+    manager.insert_prefix(_ids(7, 8), _ids(20, 21))
+
+    # lock one prefix; the flush must leave it alone
+    locked, _ = manager.match_prefix(_ids(1, 2, 3))
+    manager.lock_handle(locked)
+
+    freed = manager.clear_evictable()
+    assert sorted(freed.tolist()) == [13, 14, 20, 21]
+    assert manager.size_info.evictable_size == 0
+    assert manager.size_info.protected_size == 3
+    manager.check_integrity()
+
+    # the locked prefix still matches; an empty flush is a no-op
+    handle, _ = manager.match_prefix(_ids(1, 2, 3))
+    assert handle.cached_len == 3
+    assert len(manager.clear_evictable()) == 0
+
+
+@call_if_main()
+def test_recompute_sizes():
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2, 3), _ids(10, 11, 12))
+    handle = manager.preload(_ids(5, 6), _ids(20, 21))
+    manager.check_integrity()
+
+    # deliberately desync the incremental counters (as a buggy restore would)
+    manager.evictable_size += 7
+    manager.protected_size -= 1
+    try:
+        manager.check_integrity()
+        raise AssertionError("expected AssertionError")
+    except AssertionError as e:
+        assert "desynced" in str(e)
+
+    info = manager.recompute_sizes()
+    assert info.evictable_size == 3 and info.protected_size == 2
+    assert manager.size_info == info
+    manager.check_integrity()
+
+    manager.lock_handle(handle, unlock=True)
+    manager.check_integrity()
+
+
+@call_if_main()
+def test_insert_from_handle():
+    via_handle = RadixCacheManager(torch.device("cpu"))
+    via_handle.insert_prefix(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+    handle, _ = via_handle.match_prefix(_ids(1, 2, 3, 4))
+    via_handle.insert_from_handle(handle, _ids(5, 6), _ids(14, 15))
+
+    two_calls = RadixCacheManager(torch.device("cpu"))
+    two_calls.insert_prefix(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+    two_calls.insert_prefix(_ids(1, 2, 3, 4, 5, 6), _ids(10, 11, 12, 13, 14, 15))
+
+    for query in [_ids(1, 2, 3, 4, 5, 6), _ids(1, 2, 3, 4, 5, 9)]:
+        lhs_handle, lhs_indices = via_handle.match_prefix(query)
+        rhs_handle, rhs_indices = two_calls.match_prefix(query)
+        assert lhs_handle.cached_len == rhs_handle.cached_len
+        assert lhs_indices.tolist() == rhs_indices.tolist()
+    assert via_handle.size_info == two_calls.size_info
+
+    # overlapping continuations must go through insert_prefix
+    try:
+        via_handle.insert_from_handle(handle, _ids(5, 7), _ids(24, 25))
+        raise AssertionError("expected ValueError")
+    except ValueError:
+        pass
+
+    # stale handles are rejected
+    leaf_handle, _ = via_handle.match_prefix(_ids(1, 2, 3, 4, 5, 6))
+    via_handle.evict(2)
+    try:
+        via_handle.insert_from_handle(leaf_handle, _ids(7,), _ids(30,))
+        raise AssertionError("expected ValueError")
+    except ValueError:
+        pass
+
+
+@call_if_main()
+def test_eviction_insertion_order_tiebreak():
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2), _ids(10, 11))
+    manager.insert_prefix(_ids(3, 4), _ids(20, 21))
+
+    # force equal timestamps; the earlier-inserted leaf must evict first
+    first, second = manager.root_node.children[1], manager.root_node.children[3]
+    second.timestamp = first.timestamp
+    assert first.insert_seq < second.insert_seq
+    assert sorted(manager.evict(2).tolist()) == [10, 11]
+    assert sorted(manager.evict(2).tolist()) == [20, 21]
+
+
+@call_if_main()
+def test_dense_root_children_parity():
+    dense = RadixCacheManager.with_vocab_size(torch.device("cpu"), vocab_size=128)
+    sparse = RadixCacheManager(torch.device("cpu"))
+    sequences = [
+        (_ids(1, 2, 3, 4), _ids(10, 11, 12, 13)),
+        (_ids(1, 2, 7, 8), _ids(10, 11, 22, 23)),
+        (_ids(5, 6), _ids(30, 31)),
+    ]
+    for input_ids, indices in sequences:
+        assert dense.insert_prefix(input_ids, indices) == sparse.insert_prefix(
+            input_ids, indices
+        )
+
+    for query in [_ids(1, 2, 3, 4), _ids(1, 2, 7), _ids(5, 6, 9), _ids(99)]:
+        dense_handle, dense_indices = dense.match_prefix(query)
+        sparse_handle, sparse_indices = sparse.match_prefix(query)
+        assert dense_handle.cached_len == sparse_handle.cached_len
+        assert dense_indices.tolist() == sparse_indices.tolist()
+
+    # eviction walks the dense root the same way
+    assert sorted(dense.evict(2).tolist()) == sorted(sparse.evict(2).tolist())
+
+
+@call_if_main()
+def test_match_prefix_peek():
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+    manager.insert_prefix(_ids(5, 6, 7), _ids(20, 21, 22))
+
+    # the peek reports the uncut length without splitting the node
+    assert manager.match_prefix_peek(_ids(1, 2)) == 2
+    assert manager.root_node.children[1].length == 4
+    assert manager.match_prefix_peek(_ids(1, 2, 3, 4, 9)) == 4
+    assert manager.match_prefix_peek(_ids(9, 9)) == 0
+
+    # a peeked prefix keeps its cold timestamp, so eviction still takes it
+    manager.match_prefix_peek(_ids(1, 2, 3, 4))
+    assert sorted(manager.evict(4).tolist()) == [10, 11, 12, 13]
+
+    # whereas a real match refreshes it and eviction takes the other branch
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+    manager.insert_prefix(_ids(5, 6, 7), _ids(20, 21, 22))
+    manager.match_prefix(_ids(1, 2, 3, 4))
+    assert sorted(manager.evict(3).tolist()) == [20, 21, 22]
+
+
+@call_if_main()
+def test_input_validation():
+    manager = RadixCacheManager(torch.device("cpu"))
+
+    # negative ids cannot be real tokens, on either entry point
+    for call in (
+        lambda: manager.insert_prefix(_ids(1, -2, 3), _ids(10, 11, 12)),
+        lambda: manager.match_prefix(_ids(-1,)),
+    ):
+        try:
+            call()
+            raise AssertionError("expected ValueError")
+        except ValueError:
+            pass
+
+    # insert requires one KV index per token
+    try:
+        manager.insert_prefix(_ids(1, 2, 3), _ids(10, 11))
+        raise AssertionError("expected ValueError")
+    except ValueError:
+        pass
+
+    # nothing was inserted by the rejected calls
+    assert manager.size_info.evictable_size == 0
+    assert manager.match_prefix_peek(_ids(1, 2, 3)) == 0
+
+    # a dense root also rejects out-of-vocab ids instead of an IndexError
+    dense = RadixCacheManager.with_vocab_size(torch.device("cpu"), vocab_size=16)
+    dense.insert_prefix(_ids(1, 2), _ids(10, 11))
+    for call in (
+        lambda: dense.match_prefix(_ids(1, 2, 16)),
+        lambda: dense.match_prefix_peek(_ids(99)),
+        lambda: dense.insert_prefix(_ids(16,), _ids(12,)),
+    ):
+        try:
+            call()
+            raise AssertionError("expected ValueError")
+        except ValueError:
+            pass
+    # in-vocab ids are unaffected
+    assert dense.match_prefix_peek(_ids(1, 2, 3)) == 2
+
+
+@call_if_main()
+def test_insert_or_get():
+    manager = RadixCacheManager(torch.device("cpu"))
+
+    # a fresh prefix behaves like insert_prefix and keeps the supplied indices
+    present_len, backing = manager.insert_or_get(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+    assert present_len == 0
+    assert backing.tolist() == [10, 11, 12, 13]
+
+    # a duplicate insert reports the whole prefix present with the original
+    # indices, so the caller can free its own duplicate slots
+    present_len, backing = manager.insert_or_get(_ids(1, 2, 3, 4), _ids(20, 21, 22, 23))
+    assert present_len == 4
+    assert backing.tolist() == [10, 11, 12, 13]
+    assert manager.size_info.evictable_size == 4  # nothing was re-inserted
+
+    # a partial overlap mixes existing and supplied indices at the boundary
+    present_len, backing = manager.insert_or_get(_ids(1, 2, 3, 4, 5, 6), _ids(20, 21, 22, 23, 24, 25))
+    assert present_len == 4
+    assert backing.tolist() == [10, 11, 12, 13, 24, 25]
+    assert manager.size_info.evictable_size == 6
+
+    # the tree serves matches with the same backing indices afterwards
+    handle, indices = manager.match_prefix(_ids(1, 2, 3, 4, 5, 6))
+    assert handle.cached_len == 6 and indices.tolist() == backing.tolist()
+
+
+@call_if_main()
+def test_lock_scoped():
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+    handle, _ = manager.match_prefix(_ids(1, 2, 3, 4))
+
+    with manager.lock_scoped(handle):
+        assert manager.size_info.protected_size == 4
+        assert manager.size_info.evictable_size == 0
+    assert manager.size_info.protected_size == 0
+    assert manager.size_info.evictable_size == 4
+
+    # an exception inside the block must not leak the lock
+    try:
+        with manager.lock_scoped(handle):
+            raise RuntimeError("early exit")
+    except RuntimeError:
+        pass
+    assert manager.size_info.protected_size == 0
+    assert manager.size_info.evictable_size == 4
+    manager.check_integrity()
+
+
+@call_if_main()
+def test_evict_sorted():
+    def build() -> RadixCacheManager:
+        manager = RadixCacheManager(torch.device("cpu"))
+        manager.insert_prefix(_ids(1, 2, 3, 4), _ids(21, 13, 10, 17))
+        manager.insert_prefix(_ids(5, 6), _ids(30, 4))
+        manager.insert_prefix(_ids(7,), _ids(2,))
+        return manager
+
+    plain = build().evict(7)
+    ordered = build().evict_sorted(7)
+    # same multiset of freed indices, but in ascending order
+    assert sorted(plain.tolist()) == ordered.tolist()
+    assert ordered.tolist() == sorted(ordered.tolist())
+    assert ordered.tolist() == [2, 4, 10, 13, 17, 21, 30]
+
+
+@call_if_main()
+def test_node_id_recycling():
+    from minisgl.kvcache.radix_manager import RadixTreeNode
+
+    manager = RadixCacheManager(torch.device("cpu"), recycle_node_ids=True)
+    manager.insert_prefix(_ids(1, 2), _ids(10, 11))
+    baseline = RadixTreeNode.counter
+
+    # heavy insert/evict churn reuses ids instead of growing the counter
+    for step in range(50):
+        manager.evict(2)
+        manager.insert_prefix(_ids(1, step + 3), _ids(10, 11))
+    assert RadixTreeNode.counter == baseline
+
+    # eviction ordering still follows insertion order despite reused ids
+    manager.insert_prefix(_ids(7, 8), _ids(20, 21))
+    first_evicted = manager.evict(2).tolist()
+    assert sorted(first_evicted) == [10, 11]
+    assert sorted(manager.evict(2).tolist()) == [20, 21]
+
+    # the default never feeds the free list, keeping ids unique for debugging
+    plain = RadixCacheManager(torch.device("cpu"))
+    plain.insert_prefix(_ids(1,), _ids(10,))
+    plain.evict(1)
+    assert plain._free_node_ids == []
+
+    # the free list is per manager: another tree's inserts draw fresh ids
+    # instead of consuming ids evicted from the recycling manager above
+    assert manager._free_node_ids
+    before = RadixTreeNode.counter
+    other = RadixCacheManager(torch.device("cpu"), recycle_node_ids=True)
+    other.insert_prefix(_ids(1, 2), _ids(10, 11))
+    assert RadixTreeNode.counter > before
+    assert manager._free_node_ids  # untouched
+
+
+@call_if_main()
+def test_evict_nodes():
+    manager = RadixCacheManager(torch.device("cpu"))
+    manager.insert_prefix(_ids(1, 2, 3), _ids(10, 11, 12))
+    manager.insert_prefix(_ids(1, 2, 9), _ids(10, 11, 20))  # splits at (1, 2)
+    manager.insert_prefix(_ids(5, 6), _ids(30, 31))
+
+    leaves = {leaf.first_token: leaf for leaf in manager.evictable_leaves()}
+    # the (1, 2) branch point is not a leaf, its two continuations are
+    assert sorted(leaves) == [3, 5, 9]
+    assert leaves[5].length == 2
+
+    # a custom policy frees exactly the leaves it picked
+    freed = manager.evict_nodes([leaves[3].node_id, leaves[5].node_id])
+    assert sorted(freed.tolist()) == [12, 30, 31]
+    assert manager.size_info.evictable_size == 3  # (1, 2) split + the 9 leaf
+    manager.check_integrity()
+
+    # the remaining branch still matches
+    handle, indices = manager.match_prefix(_ids(1, 2, 9))
+    assert handle.cached_len == 3 and indices.tolist() == [10, 11, 20]
+
+    # stale ids are rejected
+    try:
+        manager.evict_nodes([leaves[3].node_id])
+        raise AssertionError("expected ValueError")
+    except ValueError as e:
+        assert "non-evictable" in str(e)
+
+    # a stale id alongside a valid one rejects the whole call: nothing is
+    # evicted, so the valid leaf's indices are not freed behind the tree
+    remaining = manager.evictable_leaves()
+    assert [leaf.first_token for leaf in remaining] == [9]
+    try:
+        manager.evict_nodes([remaining[0].node_id, leaves[3].node_id])
+        raise AssertionError("expected ValueError")
+    except ValueError:
+        pass
+    assert manager.size_info.evictable_size == 3
+    handle, indices = manager.match_prefix(_ids(1, 2, 9))
+    assert handle.cached_len == 3 and indices.tolist() == [10, 11, 20]
+    manager.check_integrity()
+
+
+@call_if_main()
+def test_match_prefix_with_depth():
+    manager = RadixCacheManager(torch.device("cpu"))
+    ids = torch.arange(8, dtype=torch.int32)
+    manager.insert_prefix(ids, torch.arange(8, dtype=torch.int32))
+
+    handle, indices, depth = manager.match_prefix_with_depth(ids)
+    assert handle.cached_len == 8 and indices.tolist() == list(range(8))
+    assert depth == 1  # one unsplit node backs the whole match
+
+    # each diverging branch splits the shared prefix one level deeper
+    for split_at, expected_depth in [(6, 2), (4, 3), (2, 4)]:
+        branch = torch.cat([ids[:split_at], _ids(100 + split_at)])
+        manager.insert_prefix(branch, torch.arange(split_at + 1, dtype=torch.int32))
+        handle, _, depth = manager.match_prefix_with_depth(ids)
+        assert handle.cached_len == 8
+        assert depth == expected_depth
+
+    # an empty match never leaves the root
+    _, _, depth = manager.match_prefix_with_depth(_ids(999))
+    assert depth == 0
@@ -0,0 +1,64 @@
+from __future__ import annotations
+
+import torch
+from minisgl.kvcache.suffix_manager import SuffixCacheManager
+from minisgl.utils import call_if_main, init_logger
+
+logger = init_logger(__name__)
+
+
+def _ids(*values: int) -> torch.Tensor:
+    return torch.tensor(values, dtype=torch.int32)
+
+
+@call_if_main()
+def test_exact_suffix_match():
+    manager = SuffixCacheManager(torch.device("cpu"))
+    manager.insert_suffix(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+
+    match = manager.match_suffix(_ids(9, 9, 1, 2, 3, 4))
+    assert match.matched_len == 4
+    # indices come back in forward order, aligned with the matched tail
+    assert match.indices.tolist() == [10, 11, 12, 13]
+
+    # a different tail matches nothing
+    match = manager.match_suffix(_ids(1, 2, 3, 5))
+    assert match.matched_len == 0 and len(match.indices) == 0
+
+
+@call_if_main()
+def test_partial_suffix_match_split():
+    manager = SuffixCacheManager(torch.device("cpu"))
+    manager.insert_suffix(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+
+    # only the last two tokens coincide, splitting the stored suffix node
+    match = manager.match_suffix(_ids(7, 8, 3, 4))
+    assert match.matched_len == 2
+    assert match.indices.tolist() == [12, 13]
+
+    # the original full suffix is still matchable after the split
+    match = manager.match_suffix(_ids(1, 2, 3, 4))
+    assert match.matched_len == 4
+    assert match.indices.tolist() == [10, 11, 12, 13]
+
+
+@call_if_main()
+def test_suffix_eviction():
+    manager = SuffixCacheManager(torch.device("cpu"))
+    manager.insert_suffix(_ids(1, 2, 3, 4), _ids(10, 11, 12, 13))
+    manager.insert_suffix(_ids(5, 6), _ids(20, 21))
+    assert manager.size_info.evictable_size == 6
+
+    # a locked suffix is protected from eviction
+    match = manager.match_suffix(_ids(5, 6))
+    manager.lock_handle(match.handle)
+    assert manager.size_info.protected_size == 2
+
+    evicted = manager.evict(4)
+    assert sorted(evicted.tolist()) == [10, 11, 12, 13]
+    assert manager.size_info.evictable_size == 0
+
+    # the protected suffix survives and matches after unlock
+    manager.lock_handle(match.handle, unlock=True)
+    assert manager.match_suffix(_ids(0, 5, 6)).matched_len == 2
+    manager.check_integrity()
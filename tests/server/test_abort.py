@@ -0,0 +1,92 @@
+from __future__ import annotations
+
+import asyncio
+from typing import List
+
+from minisgl.message import AbortMsg, BaseBackendMsg, BaseTokenizerMsg, TokenizeMsg, UserReply
+from minisgl.server.api_server import FrontendManager
+from minisgl.utils import call_if_main, init_logger
+
+logger = init_logger(__name__)
+
+
+class FakeQueue:
+    def __init__(self) -> None:
+        self.sent: List[BaseTokenizerMsg] = []
+
+    async def put(self, msg: BaseTokenizerMsg) -> None:
+        self.sent.append(msg)
+
+    async def get(self) -> BaseTokenizerMsg:
+        await asyncio.sleep(3600)
+        raise RuntimeError("unreachable")
+
+    def stop(self) -> None:
+        pass
+
+
+@call_if_main()
+def test_abort_msg_roundtrip():
+    # the abort message must decode on both the tokenizer and backend channels
+    msg = AbortMsg(uid=42)
+    for decoder in (BaseTokenizerMsg.decoder, BaseBackendMsg.decoder):
+        decoded = decoder(msg.encoder())
+        assert isinstance(decoded, AbortMsg) and decoded.uid == 42
+
+
+@call_if_main()
+def test_abort_on_disconnect():
+    import time
+
+    import minisgl.server.api_server as api_server
+    from fastapi.testclient import TestClient
+
+    send_queue = FakeQueue()
+    manager = FrontendManager(
+        config=None,  # type: ignore[arg-type]
+        send_tokenizer=send_queue,  # type: ignore[arg-type]
+        recv_tokenizer=FakeQueue(),  # type: ignore[arg-type]
+    )
+    assert api_server._GLOBAL_STATE is None
+    api_server._GLOBAL_STATE = manager
+    try:
+        with TestClient(api_server.app) as client:
+            # the client drops mid-stream: no token ever arrives, and closing
+            # the response fires the route's abort BackgroundTask
+            with client.stream("POST", "/generate", json={"prompt": "hi", "max_tokens": 4}):
+                pass
+            deadline = time.monotonic() + 2.0
+            while time.monotonic() < deadline and not any(
+                isinstance(m, AbortMsg) for m in send_queue.sent
+            ):
+                time.sleep(0.02)
+        uid = next(m.uid for m in send_queue.sent if isinstance(m, TokenizeMsg))
+        aborts = [m for m in send_queue.sent if isinstance(m, AbortMsg)]
+        assert len(aborts) == 1 and aborts[0].uid == uid
+    finally:
+        api_server._GLOBAL_STATE = None
+
+
+@call_if_main()
+def test_abort_after_finish_is_noop():
+    async def run() -> None:
+        send_queue = FakeQueue()
+        manager = FrontendManager(
+            config=None,  # type: ignore[arg-type]
+            send_tokenizer=send_queue,  # type: ignore[arg-type]
+            recv_tokenizer=FakeQueue(),  # type: ignore[arg-type]
+        )
+        uid = manager.new_user()
+        manager.ack_map[uid].append(UserReply(uid=uid, incremental_output="hi", finished=True))
+        manager.event_map[uid].set()
+        chunks = [chunk async for chunk in manager.stream_generate(uid)]
+        assert chunks[-1] == b"data: [DONE]\n"
+
+        # the stream ran to completion, so the disconnect hook must not abort
+        await manager.abort_user(uid)
+        assert not any(isinstance(m, AbortMsg) for m in send_queue.sent)
+        # and a second call (already cleaned up) stays a no-op
+        await manager.abort_user(uid)
+        assert not any(isinstance(m, AbortMsg) for m in send_queue.sent)
+
+    asyncio.run(run())
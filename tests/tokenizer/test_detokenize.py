@@ -0,0 +1,295 @@
+from __future__ import annotations
+
+from typing import List
+
+from minisgl.message import DetokenizeMsg
+from minisgl.tokenizer.detokenize import DetokenizeManager
+from minisgl.utils import call_if_main, init_logger
+
+logger = init_logger(__name__)
+
+
+class FakeTokenizer:
+    """A tiny deterministic tokenizer stub: each id maps to a fixed piece."""
+
+    eos_token_id = 0
+
+    PIECES = {
+        0: "</s>",
+        1: "hello",
+        2: " world",
+        3: "\n",
+        4: "你",
+        5: "好",
+        6: " foo",
+        7: "bar ",
+        # byte-fallback pieces for 你 (E4 BD A0)
+        8: "<0xE4>",
+        9: "<0xBD>",
+        10: "<0xA0>",
+    }
+
+    def convert_ids_to_tokens(self, token_id: int) -> str:
+        return self.PIECES[token_id]
+
+    def decode(self, ids: List[int]) -> str:
+        # assemble byte-fallback pieces into bytes like sentencepiece does
+        buffer = b""
+        for i in ids:
+            piece = self.PIECES[i]
+            if piece.startswith("<0x") and piece.endswith(">"):
+                buffer += bytes([int(piece[3:-1], 16)])
+            else:
+                buffer += piece.encode("utf-8")
+        return buffer.decode("utf-8", errors="replace")
+
+    def batch_decode(self, batch_ids: List[List[int]]) -> List[str]:
+        return [self.decode(ids) for ids in batch_ids]
+
+
+def drive_detokenize(
+    manager: DetokenizeManager, uid: int, tokens: List[int], finished_at_end: bool = True
+) -> List[str]:
+    outputs: List[str] = []
+    for i, token in enumerate(tokens):
+        finished = finished_at_end and i == len(tokens) - 1
+        outputs.extend(
+            manager.detokenize([DetokenizeMsg(uid=uid, next_token=token, finished=finished)])
+        )
+    return outputs
+
+
+@call_if_main()
+def test_hinted_manager_matches_unhinted():
+    tokens = [1, 2, 6, 7] * 500  # 2k-token sequence
+    plain = DetokenizeManager(FakeTokenizer())  # type: ignore[arg-type]
+    hinted = DetokenizeManager.new_with_hint(FakeTokenizer(), len(tokens))  # type: ignore[arg-type]
+    plain_out = drive_detokenize(plain, uid=0, tokens=tokens)
+    hinted_out = drive_detokenize(hinted, uid=0, tokens=tokens)
+    assert plain_out == hinted_out
+    assert "".join(plain_out) == FakeTokenizer().decode(tokens)
+
+
+@call_if_main()
+def test_detokenize_bytes():
+    tokens = [1, 8, 9, 10, 2]  # "hello" + 你 split across 3 byte tokens + " world"
+    manager = DetokenizeManager(FakeTokenizer())  # type: ignore[arg-type]
+    outputs: List[bytes] = []
+    for i, token in enumerate(tokens):
+        finished = i == len(tokens) - 1
+        outputs.extend(
+            manager.detokenize_bytes(
+                [DetokenizeMsg(uid=0, next_token=token, finished=finished)]
+            )
+        )
+    # every step emits its exact byte delta, no U+FFFD replacement anywhere
+    assert all(b"\xef\xbf\xbd" not in out for out in outputs)
+    assert b"".join(outputs).decode("utf-8") == FakeTokenizer().decode(tokens)
+
+
+@call_if_main()
+def test_code_mode_flush():
+    from minisgl.tokenizer.detokenize import find_printable_text
+
+    # default mode holds "foo()" until the next space; code mode flushes at ')'
+    assert find_printable_text("foo()") == ""
+    assert find_printable_text("foo()", code_mode=True) == "foo()"
+    assert find_printable_text("foo(); ba", code_mode=True) == "foo(); "
+    assert find_printable_text("if x {", code_mode=True) == "if x {"
+    # newline flushing and word boundaries are unchanged
+    assert find_printable_text("line\n", code_mode=True) == "line\n"
+    assert find_printable_text("hello wor", code_mode=True) == "hello "
+
+
+@call_if_main()
+def test_debug_self_check_random():
+    import random
+
+    rng = random.Random(0)
+    candidates = [t for t in FakeTokenizer.PIECES if t != FakeTokenizer.eos_token_id]
+    manager = DetokenizeManager(FakeTokenizer(), debug_self_check=True)  # type: ignore[arg-type]
+    for uid in range(50):
+        tokens = [rng.choice(candidates) for _ in range(rng.randint(1, 40))]
+        # the check runs after every step and raises on any offset divergence;
+        # the emitted text is always a prefix of the full decode (trailing
+        # invalid bytes may legitimately be withheld at finish)
+        outputs = drive_detokenize(manager, uid=uid, tokens=tokens)
+        assert FakeTokenizer().decode(tokens).startswith("".join(outputs))
+
+
+@call_if_main()
+def test_cumulative_mode():
+    tokens = [1, 2, 6, 7]
+    delta = DetokenizeManager(FakeTokenizer())  # type: ignore[arg-type]
+    cumulative = DetokenizeManager(FakeTokenizer(), cumulative=True)  # type: ignore[arg-type]
+
+    deltas: list[str] = []
+    for i, token in enumerate(tokens):
+        finished = i == len(tokens) - 1
+        msgs = [DetokenizeMsg(uid=0, next_token=token, finished=finished)]
+        deltas.extend(delta.detokenize(msgs))
+        # each cumulative step is the concatenation of all deltas so far
+        assert cumulative.detokenize(msgs) == ["".join(deltas)]
+
+
+@call_if_main()
+def test_flush_on_newline():
+    tokens = [1, 2, 3, 6, 3, 1]  # "hello world\n foo\nhello"
+    manager = DetokenizeManager(FakeTokenizer(), flush_on_newline=True)  # type: ignore[arg-type]
+    outputs = drive_detokenize(manager, uid=0, tokens=tokens)
+    assert outputs == ["", "", "hello world\n", "", " foo\n", "hello"]
+    # every chunk is a completed line, except the flush of a finished request
+    assert all(out.endswith("\n") for out in outputs[:-1] if out)
+    assert "".join(outputs) == FakeTokenizer().decode(tokens)
+
+
+class EagerByteTokenizer(FakeTokenizer):
+    """Decodes partial byte-fallback bytes eagerly instead of emitting U+FFFD."""
+
+    def decode(self, ids: List[int]) -> str:
+        buffer = b""
+        for i in ids:
+            piece = self.PIECES[i]
+            if piece.startswith("<0x") and piece.endswith(">"):
+                buffer += bytes([int(piece[3:-1], 16)])
+            else:
+                buffer += piece.encode("utf-8")
+        return buffer.decode("utf-8", errors="backslashreplace")
+
+
+@call_if_main()
+def test_byte_fallback_holdback():
+    tokens = [1, 8, 9, 10, 2]  # "hello" + 你 split across 3 byte tokens + " world"
+    manager = DetokenizeManager(EagerByteTokenizer())  # type: ignore[arg-type]
+    outputs = drive_detokenize(manager, uid=0, tokens=tokens)
+    # the multibyte char is held back until all its bytes arrived, so no step
+    # leaks partial bytes and the char appears exactly once
+    assert all("\\x" not in out and "�" not in out for out in outputs)
+    assert "".join(outputs).count("你") == 1
+    assert "".join(outputs) == "hello你 world"
+
+
+@call_if_main()
+def test_eos_marker():
+    tokens = [1, 2, 0]  # "hello world" followed by EOS
+    manager = DetokenizeManager(FakeTokenizer(), eos_marker="<EOS>")  # type: ignore[arg-type]
+    outputs = drive_detokenize(manager, uid=0, tokens=tokens)
+    assert "".join(outputs) == "hello world<EOS>"
+    assert "".join(outputs).count("<EOS>") == 1
+    assert outputs[-1].endswith("<EOS>")
+
+    # a non-EOS final token gets no marker
+    outputs = drive_detokenize(manager, uid=1, tokens=[1, 2])
+    assert "".join(outputs) == "hello world"
+
+    # default behavior still suppresses the EOS entirely
+    plain = DetokenizeManager(FakeTokenizer())  # type: ignore[arg-type]
+    assert "".join(drive_detokenize(plain, uid=2, tokens=tokens)) == "hello world"
+
+
+@call_if_main()
+def test_max_total_tokens_backstop():
+    manager = DetokenizeManager(FakeTokenizer(), max_total_tokens=3)  # type: ignore[arg-type]
+    tokens = [1, 2, 6, 7, 1]  # the caller never sets finished
+    reasons = []
+    for token in tokens[:3]:
+        results = manager.detokenize_full([DetokenizeMsg(uid=0, next_token=token, finished=False)])
+        reasons.append(results[0].finish_reason)
+    # the cap force-finishes the request with the length reason
+    assert reasons == [None, None, "length"]
+    assert 0 not in manager.decode_map  # state was released
+
+    # later tokens for the same uid start a fresh sequence
+    results = manager.detokenize_full([DetokenizeMsg(uid=0, next_token=tokens[3], finished=False)])
+    assert results[0].finish_reason is None
+    assert 0 in manager.decode_map
+
+    # without the cap nothing is forced
+    plain = DetokenizeManager(FakeTokenizer())  # type: ignore[arg-type]
+    for token in tokens:
+        results = plain.detokenize_full([DetokenizeMsg(uid=1, next_token=token, finished=False)])
+        assert results[0].finish_reason is None
+
+
+@call_if_main()
+def test_detokenize_stability():
+    # "hello" + "\n" + 你 split across 3 byte tokens + " world"
+    tokens = [1, 3, 8, 9, 10, 2]
+    manager = DetokenizeManager(FakeTokenizer())  # type: ignore[arg-type]
+    steps = []
+    for i, token in enumerate(tokens):
+        finished = i == len(tokens) - 1
+        steps.extend(
+            manager.detokenize_with_stability(
+                [DetokenizeMsg(uid=0, next_token=token, finished=finished)]
+            )
+        )
+
+    # the stable prefix never shrinks, and never lies
+    final_text = FakeTokenizer().decode(tokens)
+    stable_lens = [stable for _, stable in steps]
+    assert stable_lens == sorted(stable_lens)
+    for provisional, stable in steps:
+        assert final_text.startswith(provisional[:stable])
+
+    # mid-CJK: the partial char shows up beyond the stable boundary...
+    for provisional, stable in steps[2:4]:
+        assert stable == len("hello\n")
+        assert len(provisional) > stable  # the provisional replacement char
+    # ...and is only covered by the stable prefix once its bytes completed
+    provisional, stable = steps[4]
+    assert stable >= len("hello\n你")
+    assert provisional[len("hello\n")] == "你"
+
+
+@call_if_main()
+def test_detokenize_spans():
+    tokens = [1, 2, 6]
+    manager = DetokenizeManager(FakeTokenizer())  # type: ignore[arg-type]
+    cumulative = ""
+    for i, token in enumerate(tokens):
+        finished = i == len(tokens) - 1
+        results = manager.detokenize_with_spans(
+            [DetokenizeMsg(uid=0, next_token=token, finished=finished)]
+        )
+        for text, (start, end) in results:
+            # the span indexes this step's text within the cumulative output
+            assert end - start == len(text)
+            assert start == len(cumulative)
+            cumulative += text
+    assert cumulative == FakeTokenizer().decode(tokens)
+
+
+@call_if_main()
+def test_reset_reused_uid():
+    manager = DetokenizeManager(FakeTokenizer())  # type: ignore[arg-type]
+    # a stream that never delivers its finished message leaves state behind
+    manager.detokenize([DetokenizeMsg(uid=0, next_token=1, finished=False)])
+    assert 0 in manager.decode_map
+
+    # without reset, the reused uid inherits the stale prefix
+    stale = drive_detokenize(manager, uid=0, tokens=[2])
+    assert "".join(stale) == "hello world"
+
+    # reset on the first message guarantees a fresh stream
+    manager.detokenize([DetokenizeMsg(uid=0, next_token=1, finished=False)])
+    outputs = manager.detokenize([DetokenizeMsg(uid=0, next_token=2, finished=False, reset=True)])
+    outputs += manager.detokenize([DetokenizeMsg(uid=0, next_token=1, finished=True)])
+    assert "".join(outputs) == " worldhello"
+
+
+@call_if_main()
+def test_strip_leading():
+    # "bar " plays the echoed assistant header
+    manager = DetokenizeManager(FakeTokenizer(), strip_leading="bar ")  # type: ignore[arg-type]
+    outputs = drive_detokenize(manager, uid=0, tokens=[7, 1, 2])
+    # the header is gone from the first chunk, later chunks are untouched
+    assert outputs == ["", "hello", " world"]
+
+    # the same header text later in the stream is preserved
+    outputs = drive_detokenize(manager, uid=1, tokens=[7, 7, 1])
+    assert "".join(outputs) == "bar hello"
+
+    # sequences that never emit the header are unaffected
+    outputs = drive_detokenize(manager, uid=2, tokens=[1, 2])
+    assert "".join(outputs) == "hello world"
@@ -0,0 +1,224 @@
+from __future__ import annotations
+
+from typing import List
+
+import torch
+from minisgl.core import SamplingParams
+from minisgl.message import TokenizeMsg
+from minisgl.tokenizer.tokenize import (
+    EmptyMessagesError,
+    EmptyTextError,
+    TokenIdOverflowError,
+    TokenizeManager,
+    load_tokenizer,
+)
+from minisgl.utils import call_if_main, init_logger
+
+logger = init_logger(__name__)
+
+
+class FakeTokenizer:
+    def __init__(self) -> None:
+        self.encode_calls: List[str] = []
+
+    def encode(self, prompt: str, return_tensors: str = "pt") -> torch.Tensor:
+        _ = return_tensors
+        self.encode_calls.append(prompt)
+        return torch.tensor([[ord(c) for c in prompt]], dtype=torch.int64)
+
+    def apply_chat_template(
+        self,
+        messages,
+        tokenize: bool,
+        add_generation_prompt: bool,
+        chat_template: str | None = None,
+    ) -> str:
+        _ = tokenize, add_generation_prompt
+        # a crude stand-in for jinja rendering: the override joins the raw
+        # contents with itself as the separator
+        if chat_template is not None:
+            return chat_template.join(m["content"] for m in messages)
+        return "".join(f"<{m['role']}>{m['content']}" for m in messages)
+
+
+def _make_msgs(texts: List[str]) -> List[TokenizeMsg]:
+    return [
+        TokenizeMsg(uid=i, text=text, sampling_params=SamplingParams())
+        for i, text in enumerate(texts)
+    ]
+
+
+@call_if_main()
+def test_tokenize_dedup():
+    texts = ["shared"] * 5 + ["a", "b", "c"]
+    tokenizer = FakeTokenizer()
+    manager = TokenizeManager(tokenizer, dedup=True)  # type: ignore[arg-type]
+    results = manager.tokenize(_make_msgs(texts))
+    # only the unique set reaches the backend tokenizer
+    assert sorted(tokenizer.encode_calls) == sorted(set(texts))
+    # every request still gets its own result, in per-uid order
+    assert len(results) == len(texts)
+    for text, ids in zip(texts, results, strict=True):
+        assert ids.tolist() == [ord(c) for c in text]
+
+    # default mode encodes each prompt separately
+    tokenizer = FakeTokenizer()
+    manager = TokenizeManager(tokenizer)  # type: ignore[arg-type]
+    manager.tokenize(_make_msgs(texts))
+    assert len(tokenizer.encode_calls) == len(texts)
+
+
+class OverflowTokenizer(FakeTokenizer):
+    def encode(self, prompt: str, return_tensors: str = "pt") -> torch.Tensor:
+        _ = prompt, return_tensors
+        return torch.tensor([[1 << 40]], dtype=torch.int64)
+
+
+@call_if_main()
+def test_error_types():
+    # oversized token ids surface as OverflowError (and TokenizerError)
+    manager = TokenizeManager(OverflowTokenizer())  # type: ignore[arg-type]
+    try:
+        manager.tokenize(_make_msgs(["hi"]))
+        raise AssertionError("expected TokenIdOverflowError")
+    except OverflowError as e:
+        assert isinstance(e, TokenIdOverflowError)
+
+    # empty-input errors remain plain ValueErrors for callers that catch broadly
+    assert issubclass(EmptyMessagesError, ValueError)
+    assert issubclass(EmptyTextError, ValueError)
+
+    # a missing local path is a FileNotFoundError, not a backend-specific error
+    for path in ("/nonexistent/path/to/model", "./nonexistent/model"):
+        try:
+            load_tokenizer(path)
+            raise AssertionError("expected FileNotFoundError")
+        except FileNotFoundError:
+            pass
+
+    # a hub-style repo id also contains a separator but is not a local path;
+    # it falls through to the backend, whose failure is wrapped uniformly
+    try:
+        load_tokenizer("no-such-org/no-such-model")
+        raise AssertionError("expected RuntimeError")
+    except RuntimeError:
+        pass
+
+
+class PairTokenizer(FakeTokenizer):
+    """Merges char pairs into one token, so boundaries can fall mid-token."""
+
+    def encode(self, prompt: str, return_tensors: str = "pt") -> torch.Tensor:
+        _ = return_tensors
+        self.encode_calls.append(prompt)
+        ids = [
+            ord(prompt[i]) * 1000 + (ord(prompt[i + 1]) if i + 1 < len(prompt) else 0)
+            for i in range(0, len(prompt), 2)
+        ]
+        return torch.tensor([ids], dtype=torch.int64)
+
+
+@call_if_main()
+def test_encode_suffix():
+    tokenizer = FakeTokenizer()
+    manager = TokenizeManager(tokenizer)  # type: ignore[arg-type]
+    full = "the quick brown fox jumps over the lazy dog"
+    for prefix_len in [0, 1, 20, len(full)]:
+        prefix = full[:prefix_len]
+        suffix_ids = manager.encode_suffix(full, prefix)
+        joined = manager._encode(prefix).tolist() + suffix_ids.tolist()
+        assert joined == manager._encode(full).tolist()
+    # with the cached ids supplied, only the suffix plus the backoff window is
+    # re-encoded, never the whole prefix or the full text
+    prefix_ids = manager._encode(full[:30])
+    tokenizer.encode_calls.clear()
+    manager.encode_suffix(full, full[:30], cached_prefix_ids=prefix_ids)
+    assert all(len(call) <= len(full) - 30 + 16 for call in tokenizer.encode_calls)
+
+    # a prefix that is not a prefix at all is rejected
+    try:
+        manager.encode_suffix(full, "unrelated")
+        raise AssertionError("expected ValueError")
+    except ValueError as e:
+        assert "not a prefix" in str(e)
+
+    # a boundary inside a merged token is not a token-boundary prefix
+    pair_manager = TokenizeManager(PairTokenizer())  # type: ignore[arg-type]
+    try:
+        pair_manager.encode_suffix("abcd", "abc")
+        raise AssertionError("expected ValueError")
+    except ValueError as e:
+        assert "token-boundary" in str(e)
+    # an even split lands on a pair boundary and round-trips
+    suffix_ids = pair_manager.encode_suffix("abcd", "ab")
+    joined = pair_manager._encode("ab").tolist() + suffix_ids.tolist()
+    assert joined == pair_manager._encode("abcd").tolist()
+
+
+@call_if_main()
+def test_empty_inputs_rejected():
+    manager = TokenizeManager(FakeTokenizer())  # type: ignore[arg-type]
+    empty_chat = [TokenizeMsg(uid=0, text=[], sampling_params=SamplingParams())]
+    try:
+        manager.tokenize(empty_chat)
+        raise AssertionError("empty messages should be rejected")
+    except EmptyMessagesError:
+        pass
+
+    # empty text is allowed by default but can be denied
+    assert len(manager.tokenize(_make_msgs([""]))) == 1
+    strict = TokenizeManager(FakeTokenizer(), allow_empty_text=False)  # type: ignore[arg-type]
+    try:
+        strict.tokenize(_make_msgs([""]))
+        raise AssertionError("empty text should be rejected")
+    except EmptyTextError:
+        pass
+
+
+@call_if_main()
+def test_chat_template_override():
+    manager = TokenizeManager(FakeTokenizer())  # type: ignore[arg-type]
+    messages = [{"role": "user", "content": "hi"}, {"role": "assistant", "content": "yo"}]
+
+    default = TokenizeMsg(uid=0, text=messages, sampling_params=SamplingParams())
+    raw = TokenizeMsg(uid=1, text=messages, sampling_params=SamplingParams(), chat_template="\n")
+    default_ids, raw_ids = manager.tokenize([default, raw])
+
+    # the default template keeps the role markers; the override drops them
+    assert bytes(default_ids.tolist()).decode() == "<user>hi<assistant>yo"
+    assert bytes(raw_ids.tolist()).decode() == "hi\nyo"
+
+    # plain-text prompts are unaffected by the field
+    text = TokenizeMsg(uid=2, text="plain", sampling_params=SamplingParams(), chat_template="\n")
+    assert manager.tokenize([text])[0].tolist() == [ord(c) for c in "plain"]
+
+
+@call_if_main()
+def test_validate_unique_uids():
+    from minisgl.message import DetokenizeMsg
+    from minisgl.tokenizer.detokenize import DetokenizeManager
+    from minisgl.tokenizer.tokenize import validate_unique_uids
+
+    msgs = _make_msgs(["a", "b", "c"])
+    assert validate_unique_uids(msgs) is None
+    msgs[2].uid = msgs[0].uid
+    assert validate_unique_uids(msgs) == msgs[0].uid
+
+    # both managers reject duplicate uids before touching any state
+    manager = TokenizeManager(FakeTokenizer())  # type: ignore[arg-type]
+    try:
+        manager.tokenize(msgs)
+        raise AssertionError("expected ValueError")
+    except ValueError as e:
+        assert "Duplicate uid" in str(e)
+
+    tokenizer = FakeTokenizer()
+    tokenizer.eos_token_id = 0  # type: ignore[attr-defined]
+    detok = DetokenizeManager(tokenizer)  # type: ignore[arg-type]
+    dup = [DetokenizeMsg(uid=0, next_token=1, finished=False)] * 2
+    try:
+        detok.detokenize(dup)
+        raise AssertionError("expected ValueError")
+    except ValueError as e:
+        assert "Duplicate uid" in str(e)
+    assert 0 not in detok.decode_map